@@ -0,0 +1,261 @@
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+// `pm agent` keeps a password file open in the background so that it doesn't
+// have to be read, parsed, and, if it's encrypted, unlocked for every command.
+// Commands ask it for the entries over a Unix socket and tell it about
+// anything they save. If it isn't running, or it has some other file, they
+// read the file themselves like always.
+//
+// Each request and response is a line of JSON.
+#[derive(serde_derive::Serialize, serde_derive::Deserialize, Debug, PartialEq)]
+pub enum Request {
+    Get { file: PathBuf },
+    Put { file: PathBuf, recipients: Vec<pm::vault::Recipient>, entries: pm::Entries },
+    Lock,
+}
+
+#[derive(serde_derive::Serialize, serde_derive::Deserialize, Debug, PartialEq)]
+pub enum Response {
+    Unlocked { recipients: Vec<pm::vault::Recipient>, entries: pm::Entries },
+    // The agent has a different file, or the file was changed by something
+    // that didn't tell the agent.
+    Elsewhere,
+    Done,
+}
+
+// What the agent holds on to.
+#[derive(Debug)]
+pub struct State {
+    pub file: PathBuf,
+    pub modified: Option<SystemTime>,
+    pub recipients: Vec<pm::vault::Recipient>,
+    pub entries: pm::Entries,
+}
+
+// Where the agent listens. It's in XDG_RUNTIME_DIR, which only we can get into,
+// or else a directory of our own in /tmp.
+pub fn socket_path() -> PathBuf {
+    if let Some(socket) = std::env::var_os("PM_AGENT_SOCKET") {
+        return PathBuf::from(socket);
+    }
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join("pm-agent.sock"),
+        _ => std::env::temp_dir().join(format!("pm-{}", unsafe { libc::getuid() })).join("agent.sock"),
+    }
+}
+
+// The file is compared by path, so it has to be spelled the same way every
+// time.
+pub fn canonical(file: &Path) -> PathBuf {
+    file.canonicalize().unwrap_or_else(|_| file.to_owned())
+}
+
+pub fn modified(file: &Path) -> Option<SystemTime> {
+    std::fs::metadata(file).and_then(|metadata| metadata.modified()).ok()
+}
+
+// Answers a request. Returns false once it's time to stop.
+pub fn handle(state: &mut State, request: Request) -> (Response, bool) {
+    match request {
+        Request::Get { file } => {
+            if file == state.file && modified(&file) == state.modified {
+                (Response::Unlocked{recipients: state.recipients.clone(), entries: state.entries.clone()}, true)
+            } else {
+                (Response::Elsewhere, true)
+            }
+        },
+        Request::Put { file, recipients, entries } => {
+            if file == state.file {
+                state.modified = modified(&file);
+                state.recipients = recipients;
+                state.entries = entries;
+                (Response::Done, true)
+            } else {
+                (Response::Elsewhere, true)
+            }
+        },
+        Request::Lock => (Response::Done, false),
+    }
+}
+
+// Answers requests until it's locked or nobody has asked for anything in
+// `idle`. Connections from other users are hung up on.
+pub fn serve(listener: UnixListener, mut state: State, idle: Duration) {
+    listener.set_nonblocking(true).expect("Failed setting up the agent's socket.");
+    let mut last_used = Instant::now();
+    loop {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                last_used = Instant::now();
+                if !same_user(&stream) {
+                    continue;
+                }
+                let _ = stream.set_nonblocking(false);
+                let _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
+                let mut line = String::new();
+                if BufReader::new(&stream).read_line(&mut line).is_err() {
+                    continue;
+                }
+                let request = match serde_json::from_str(&line) {
+                    Ok(request) => request,
+                    Err(_) => continue,
+                };
+                let (response, keep_going) = handle(&mut state, request);
+                let _ = writeln!(&stream, "{}", serde_json::to_string(&response).expect("Failed serializing a response."));
+                if !keep_going {
+                    return;
+                }
+            },
+            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                if last_used.elapsed() > idle {
+                    return;
+                }
+                std::thread::sleep(Duration::from_millis(50));
+            },
+            Err(_) => std::thread::sleep(Duration::from_millis(50)),
+        }
+    }
+}
+
+// Checks that whoever connected is running as us. The socket's permissions
+// should already see to that, but this doesn't depend on them.
+pub fn same_user(stream: &UnixStream) -> bool {
+    let mut credentials = libc::ucred{pid: 0, uid: 0, gid: 0};
+    let mut length = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut credentials as *mut libc::ucred as *mut libc::c_void,
+            &mut length,
+        )
+    };
+    result == 0 && credentials.uid == unsafe { libc::getuid() }
+}
+
+// Keeps the agent's memory out of swap and core dumps, and other processes
+// from attaching to it. It's done before anything is decrypted into it.
+pub fn protect_memory() -> Result<(), String> {
+    unsafe {
+        libc::prctl(libc::PR_SET_DUMPABLE, 0, 0, 0, 0);
+        let no_core = libc::rlimit{rlim_cur: 0, rlim_max: 0};
+        libc::setrlimit(libc::RLIMIT_CORE, &no_core);
+        if libc::mlockall(libc::MCL_CURRENT | libc::MCL_FUTURE) != 0 {
+            return Err(format!("Failed locking the agent's memory: {}", std::io::Error::last_os_error()));
+        }
+    }
+    Ok(())
+}
+
+// Makes the socket, readable and writable only by us. A socket left behind
+// by an agent that didn't get to clean up is replaced, but not one that's
+// still answering.
+pub fn listen(socket: &Path) -> Result<UnixListener, String> {
+    if let Some(dir) = socket.parent() {
+        std::fs::DirBuilder::new().recursive(true).mode(0o700).create(dir)
+            .map_err(|e| format!("Failed creating {}: {}", dir.display(), e))?;
+    }
+    if UnixStream::connect(socket).is_ok() {
+        return Err(String::from("An agent is already running. Run `pm lock` to stop it first."));
+    }
+    let _ = std::fs::remove_file(socket);
+    // The umask makes the socket 0600 from the start instead of after a chmod.
+    let old_umask = unsafe { libc::umask(0o177) };
+    let listener = UnixListener::bind(socket);
+    unsafe { libc::umask(old_umask) };
+    listener.map_err(|e| format!("Failed listening on {}: {}", socket.display(), e))
+}
+
+// Sends a request. There's no response if there's no agent.
+pub fn request(socket: &Path, request: &Request) -> Option<Response> {
+    let stream = UnixStream::connect(socket).ok()?;
+    stream.set_read_timeout(Some(Duration::from_secs(5))).ok()?;
+    writeln!(&stream, "{}", serde_json::to_string(request).expect("Failed serializing a request.")).ok()?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line).ok()?;
+    serde_json::from_str(&line).ok()
+}
+
+// Gets the entries from the agent, if it has this file.
+pub fn fetch(socket: &Path, file: &Path) -> Option<(pm::Entries, pm::vault::Vault)> {
+    match request(socket, &Request::Get{file: canonical(file)})? {
+        Response::Unlocked { recipients, entries } => Some((entries, pm::vault::Vault{path: file.to_owned(), recipients})),
+        _ => None,
+    }
+}
+
+// Tells the agent about something that was just saved, so that it doesn't
+// hand out what was there before.
+pub fn update(socket: &Path, vault: &pm::vault::Vault, entries: &pm::Entries) {
+    request(socket, &Request::Put{file: canonical(&vault.path), recipients: vault.recipients.clone(), entries: entries.clone()});
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static S: fn(&'static str) -> String = String::from;
+
+    fn state(file: &Path) -> State {
+        State{
+            file: file.to_owned(),
+            modified: modified(file),
+            recipients: vec![],
+            entries: pm::Entries::new().update(S("a"), pm::Entry{name: S("a"), password: S("a password"), ..pm::Entry::default()}),
+        }
+    }
+
+    #[test]
+    fn test_handle() {
+        let temp = mktemp::Temp::new_file().unwrap();
+        std::fs::write(&temp, "{}").unwrap();
+        let file = canonical(&temp);
+        let mut state = state(&file);
+
+        let (response, keep_going) = handle(&mut state, Request::Get{file: file.clone()});
+        assert_eq!(response, Response::Unlocked{recipients: vec![], entries: state.entries.clone()});
+        assert!(keep_going);
+        assert_eq!(handle(&mut state, Request::Get{file: PathBuf::from("/elsewhere")}).0, Response::Elsewhere);
+
+        // Something else wrote to the file, so what the agent has is stale.
+        state.modified = Some(SystemTime::UNIX_EPOCH);
+        assert_eq!(handle(&mut state, Request::Get{file: file.clone()}).0, Response::Elsewhere);
+
+        // A put from pm itself brings it up to date.
+        assert_eq!(handle(&mut state, Request::Put{file: file.clone(), recipients: vec![], entries: pm::Entries::new()}).0, Response::Done);
+        assert_eq!(handle(&mut state, Request::Get{file: file.clone()}).0, Response::Unlocked{recipients: vec![], entries: pm::Entries::new()});
+        assert_eq!(handle(&mut state, Request::Put{file: PathBuf::from("/elsewhere"), recipients: vec![], entries: pm::Entries::new()}).0, Response::Elsewhere);
+
+        assert_eq!(handle(&mut state, Request::Lock), (Response::Done, false));
+    }
+
+    #[test]
+    fn test_serve() {
+        let dir = mktemp::Temp::new_dir().unwrap();
+        let socket = dir.join("agent.sock");
+        let temp = mktemp::Temp::new_file().unwrap();
+        std::fs::write(&temp, "{}").unwrap();
+        let state = state(&canonical(&temp));
+        let entries = state.entries.clone();
+
+        let listener = listen(&socket).unwrap();
+        let server = std::thread::spawn(move || serve(listener, state, Duration::from_secs(10)));
+        assert!(listen(&socket).is_err());
+        assert_eq!(fetch(&socket, &temp).map(|(entries, _)| entries), Some(entries));
+        assert_eq!(fetch(&socket, Path::new("/elsewhere")), None);
+        assert_eq!(request(&socket, &Request::Lock), Some(Response::Done));
+        server.join().unwrap();
+        assert_eq!(fetch(&socket, &temp), None);
+
+        // Nobody asking for anything stops it too.
+        let listener = listen(&socket).unwrap();
+        let state = State{file: PathBuf::from("/elsewhere"), modified: None, recipients: vec![], entries: pm::Entries::new()};
+        serve(listener, state, Duration::from_millis(100));
+    }
+}
@@ -1,16 +1,47 @@
 use std::{thread, time};
-use std::io::{stdin, stdout};
+use std::io::{stdin, stdout, Read, Write};
+use std::os::unix::process::CommandExt;
 
 use structopt::StructOpt;
-use clipboard::{ClipboardContext,ClipboardProvider};
+
+use crate::agent;
+use crate::clipboards::{self, Clipboard};
+use crate::exec;
+use crate::server;
+use crate::shell;
+use crate::tui;
 
 use pm::EntriesStuff;
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "pm", about = "A password manager.", author="Mason Staugler<@mqsoh>")]
+#[structopt(after_help = "ENTRIES:
+    Commands that take an entry accept any of these.
+
+        #3          the third entry, as numbered by `list`
+        id:1a2b3c4d the entry with that ID
+        =1234       the entry named \"1234\"
+
+    Anything else is tried as an ID, then as a name, and then as an index.")]
 struct Opts {
     #[structopt(parse(from_os_str))]
     filename: std::path::PathBuf,
+    /// A key file from `pm keygen`, for opening an encrypted file or a bundle
+    /// someone shared with you.
+    #[structopt(long = "identity", env = "PM_IDENTITY", parse(from_os_str), raw(global = "true"))]
+    identity: Option<std::path::PathBuf>,
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+// A line in the shell. It's the same commands without the file name in front.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "pm", raw(setting = "structopt::clap::AppSettings::NoBinaryName"))]
+#[structopt(after_help = "SHELL:
+    save [--force]  write the changes to the file
+    undo            take back the last change
+    exit            leave, asking first if there are unsaved changes")]
+struct ShellLine {
     #[structopt(subcommand)]
     command: Command,
 }
@@ -18,75 +49,1135 @@ struct Opts {
 #[derive(Debug, StructOpt)]
 enum Command {
     #[structopt(name = "list")]
-    List,
+    List {
+        /// Only list entries in this folder, like "work/aws". It's the same
+        /// as --folder.
+        #[structopt(name = "FOLDER")]
+        in_folder: Option<String>,
+        #[structopt(flatten)]
+        filter: FilterFlags,
+    },
     #[structopt(name = "add")]
     Add,
     #[structopt(name = "show")]
     Show { entry: String, },
     #[structopt(name = "edit")]
-    Edit { entry: String, },
+    Edit {
+        entry: String,
+        /// Edit the whole entry at once as TOML in $VISUAL or $EDITOR, which
+        /// is how to get more than one line into the notes.
+        #[structopt(long = "editor")]
+        editor: bool,
+    },
     #[structopt(name = "delete")]
     Delete { entry: String },
     #[structopt(name = "clip")]
-    Clip { entry: String },
+    Clip {
+        entry: String,
+        /// What to copy: password, username, notes, or a custom field.
+        #[structopt(long = "field", default_value = "password")]
+        field: String,
+        /// Copy the username and then, once it's been pasted, the password.
+        #[structopt(long = "sequence", raw(conflicts_with = r#""field""#))]
+        sequence: bool,
+        /// How many seconds to leave the password in the clipboard.
+        #[structopt(long = "timeout", default_value = "10", env = "PM_CLIP_TIMEOUT")]
+        timeout: u64,
+        /// Which clipboard to use: wayland, xclip, xsel, x11, primary, tmux,
+        /// or osc52. It's picked based on the environment if it isn't given.
+        #[structopt(long = "clipboard", env = "PM_CLIPBOARD")]
+        clipboard: Option<clipboards::Backend>,
+    },
+    /// What `clip` runs in the background to hold the password in the
+    /// clipboard and clear it later. The password is read from stdin.
+    #[structopt(name = "clear-clipboard", raw(setting = "structopt::clap::AppSettings::Hidden"))]
+    ClearClipboard {
+        #[structopt(long = "after")]
+        after: u64,
+        #[structopt(long = "clipboard")]
+        clipboard: clipboards::Backend,
+    },
     #[structopt(name = "print")]
     Print { entry: String },
+    #[structopt(name = "mv")]
+    Mv {
+        entry: String,
+        /// The new name. End it with a slash, like "work/", to move the entry
+        /// into that folder and keep its name.
+        destination: String,
+        /// Replace an entry that already has the new name.
+        #[structopt(long = "force")]
+        force: bool,
+    },
+    #[structopt(name = "import")]
+    Import {
+        /// The kind of export: keepass-xml, bitwarden-json, bitwarden-csv,
+        /// 1password-1pux, 1password-csv, browser-csv (Chrome and Firefox),
+        /// or pass. keepass-xml is KeePass's XML export; a .kdbx database has
+        /// to be exported as XML first.
+        #[structopt(long = "format")]
+        format: pm::import::Format,
+        /// The export, or the directory of a pass password store.
+        #[structopt(parse(from_os_str))]
+        file: std::path::PathBuf,
+        /// What to do when an imported entry has the same name as one that's
+        /// already there: prompt, suffix, or skip.
+        #[structopt(long = "on-collision", default_value = "prompt")]
+        on_collision: pm::import::Collision,
+        /// Show what would be imported without saving anything.
+        #[structopt(long = "dry-run")]
+        dry_run: bool,
+        /// The program that decrypts a pass password store.
+        #[structopt(long = "gpg", default_value = "gpg", env = "PM_GPG")]
+        gpg: String,
+    },
+    #[structopt(name = "export")]
+    Export {
+        /// The kind of export: csv, json, jsonl, or pass.
+        #[structopt(long = "format")]
+        format: pm::export::Format,
+        /// Where to write it. It's stdout if there isn't one, except for pass,
+        /// where it's the directory of the password store, which needs a
+        /// .gpg-id. Files that are already there are replaced.
+        #[structopt(parse(from_os_str))]
+        destination: Option<std::path::PathBuf>,
+        /// Which fields to export, separated by commas, like
+        /// "name,username,url". It's every field if there aren't any.
+        #[structopt(long = "fields", raw(use_delimiter = "true"))]
+        fields: Vec<String>,
+        /// Leave out passwords, notes, and custom fields other than URLs.
+        /// Otherwise, you'll be asked to confirm.
+        #[structopt(long = "no-secrets")]
+        no_secrets: bool,
+        #[structopt(flatten)]
+        filter: FilterFlags,
+        /// The program that encrypts a pass password store.
+        #[structopt(long = "gpg", default_value = "gpg", env = "PM_GPG")]
+        gpg: String,
+    },
+    /// Make a key for receiving shared entries. Give the public key to
+    /// whoever is sharing with you.
+    #[structopt(name = "keygen")]
+    Keygen {
+        /// Where to write the key. It's stdout if there isn't one.
+        #[structopt(short = "o", long = "output", parse(from_os_str))]
+        output: Option<std::path::PathBuf>,
+    },
+    /// Encrypt entries into a bundle for someone else to receive.
+    #[structopt(name = "share")]
+    Share {
+        #[structopt(raw(required = "true"))]
+        entries: Vec<String>,
+        /// Who can open the bundle: an age public key, like "age1...", or a
+        /// file with one per line. Give it more than once for more people.
+        #[structopt(long = "to", raw(required = "true"))]
+        to: Vec<String>,
+        #[structopt(short = "o", long = "output", parse(from_os_str))]
+        output: std::path::PathBuf,
+    },
+    /// Add the entries from a bundle someone shared with you.
+    #[structopt(name = "receive")]
+    Receive {
+        #[structopt(parse(from_os_str))]
+        bundle: std::path::PathBuf,
+        /// What to do when a shared entry has the same name as one that's
+        /// already there: prompt, suffix, or skip.
+        #[structopt(long = "on-collision", default_value = "prompt")]
+        on_collision: pm::import::Collision,
+        /// Show what would be added without saving anything.
+        #[structopt(long = "dry-run")]
+        dry_run: bool,
+    },
+    /// Keep the file open in the background so that other commands don't
+    /// have to read it, or ask for a passphrase, every time.
+    #[structopt(name = "agent")]
+    Agent {
+        /// Stop after this many seconds without being used.
+        #[structopt(long = "timeout", default_value = "900", env = "PM_AGENT_TIMEOUT")]
+        timeout: u64,
+        /// What `agent` runs in the background. The entries are read from
+        /// stdin.
+        #[structopt(long = "foreground", raw(hidden = "true"))]
+        foreground: bool,
+    },
+    /// Stop the agent, forgetting everything it had.
+    #[structopt(name = "lock")]
+    Lock,
+    /// Manage who can open an encrypted password file. Adding the first
+    /// recipient encrypts it.
+    #[structopt(name = "recipients")]
+    Recipients {
+        #[structopt(subcommand)]
+        command: RecipientsCommand,
+    },
+    /// Be git's credential helper. Entries are found by their url fields,
+    /// so set it up with
+    /// `git config credential.helper "!pm /path/to/file credential"`.
+    #[structopt(name = "credential")]
+    Credential {
+        /// What git wants: get, store, or erase.
+        action: String,
+    },
+    /// Run a command with values from entries in its environment, like
+    /// `pm file exec --env TOKEN=github:password -- make deploy`. It exits
+    /// with the command's exit code.
+    #[structopt(name = "exec")]
+    Exec {
+        /// A variable to set, like NAME=entry:field. Give it more than once
+        /// for more variables.
+        #[structopt(long = "env", raw(number_of_values = "1"))]
+        env: Vec<exec::Variable>,
+        #[structopt(raw(required = "true", last = "true"))]
+        command: Vec<String>,
+    },
+    /// Answer JSON over HTTP, for programs that want entries without
+    /// running pm. GET /entries lists them, GET /entries/<entry> gets one,
+    /// POST /entries makes one, PUT changes one, and DELETE deletes one.
+    #[structopt(name = "serve")]
+    Serve {
+        /// A Unix socket to listen on. Only you can connect to it.
+        #[structopt(long = "socket", parse(from_os_str), raw(required_unless = r#""listen""#, conflicts_with = r#""listen""#))]
+        socket: Option<std::path::PathBuf>,
+        /// A loopback address to listen on, like 127.0.0.1:8750. Requests
+        /// need the token.
+        #[structopt(long = "listen")]
+        listen: Option<std::net::SocketAddr>,
+        /// The bearer token that requests need. With --listen, one is made
+        /// up and printed if it isn't given.
+        #[structopt(long = "token", env = "PM_SERVE_TOKEN", raw(hide_env_values = "true"))]
+        token: Option<String>,
+    },
+    /// Keep the file open and run commands on it one after another. Changes
+    /// are only written with `save`, unless there's --autosave, and `undo`
+    /// takes them back one at a time.
+    #[structopt(name = "shell")]
+    Shell {
+        /// Save after every change.
+        #[structopt(long = "autosave")]
+        autosave: bool,
+    },
+    /// Browse the entries full screen. Type / to narrow them down, enter to
+    /// copy the password, u for the username, and a, e, or d to add, edit,
+    /// or delete.
+    #[structopt(name = "tui")]
+    Tui {
+        /// How many seconds to leave the password in the clipboard.
+        #[structopt(long = "timeout", default_value = "10", env = "PM_CLIP_TIMEOUT")]
+        timeout: u64,
+        /// Which clipboard to use, the same as for clip.
+        #[structopt(long = "clipboard", env = "PM_CLIPBOARD")]
+        clipboard: Option<clipboards::Backend>,
+    },
+    /// Load an SSH key entry into ssh-agent, through SSH_AUTH_SOCK.
+    #[structopt(name = "ssh-add")]
+    SshAdd {
+        entry: String,
+        /// Have the agent forget the key after this many seconds.
+        #[structopt(short = "t", long = "lifetime")]
+        lifetime: Option<u32>,
+    },
+    /// Make a new Ed25519 SSH key in a new entry and print its public key.
+    #[structopt(name = "ssh-keygen")]
+    SshKeygen {
+        name: String,
+        /// What goes at the end of the public key. It's the entry's name if
+        /// there isn't one.
+        #[structopt(short = "C", long = "comment")]
+        comment: Option<String>,
+    },
+    /// Write entries' passwords out as environment variables. Each one is
+    /// named by the entry's env field or, without one, its name, so
+    /// "myservice/db-password" is MYSERVICE_DB_PASSWORD.
+    #[structopt(name = "env")]
+    Env {
+        /// dotenv, sh, fish, or json.
+        #[structopt(long = "format", default_value = "dotenv")]
+        format: pm::environment::Format,
+        #[structopt(flatten)]
+        filter: FilterFlags,
+    },
+    /// Fill in a template with values from entries. A reference looks like
+    /// {{ pm "db-prod" "password" }}, and the field can be left out to mean
+    /// the password.
+    #[structopt(name = "inject")]
+    Inject {
+        #[structopt(short = "i", long = "input", parse(from_os_str))]
+        input: std::path::PathBuf,
+        /// Where to write it, readable only by you. It's stdout if there
+        /// isn't one.
+        #[structopt(short = "o", long = "output", parse(from_os_str))]
+        output: Option<std::path::PathBuf>,
+    },
+    #[structopt(name = "tags")]
+    Tags,
+    #[structopt(name = "tag")]
+    Tag {
+        #[structopt(subcommand)]
+        command: TagCommand,
+    },
+}
+
+// The flags that narrow down which entries list, export, and env work on.
+#[derive(Debug, StructOpt)]
+struct FilterFlags {
+    /// Only include entries in this folder, like "work/aws".
+    #[structopt(long = "folder")]
+    folder: Option<String>,
+    /// Only include entries with this tag. Give it more than once to require
+    /// all of them.
+    #[structopt(long = "tag")]
+    tag: Vec<String>,
+    /// Only include entries with at least one of these tags.
+    #[structopt(long = "any-tag")]
+    any_tag: Vec<String>,
+    /// Only include entries with this in their name or username.
+    #[structopt(long = "search")]
+    search: Option<String>,
+}
+
+impl FilterFlags {
+    fn filter(self) -> pm::Filter {
+        pm::Filter{
+            folder: self.folder.unwrap_or_default(),
+            all_tags: self.tag,
+            any_tags: self.any_tag,
+            search: self.search.unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+enum TagCommand {
+    #[structopt(name = "add")]
+    Add { tag: String, entries: Vec<String> },
+    #[structopt(name = "remove")]
+    Remove { tag: String, entries: Vec<String> },
+}
+
+#[derive(Debug, StructOpt)]
+enum RecipientsCommand {
+    #[structopt(name = "list")]
+    List,
+    #[structopt(name = "add")]
+    Add {
+        name: String,
+        /// Their age public key, like "age1...", or a file with it in it.
+        #[structopt(long = "key", raw(conflicts_with = r#""passphrase""#))]
+        key: Option<String>,
+        /// Let them open it with a passphrase instead, which you'll be asked
+        /// for.
+        #[structopt(long = "passphrase")]
+        passphrase: bool,
+    },
+    /// Take someone off the list and encrypt the file again without them.
+    #[structopt(name = "remove")]
+    Remove { name: String },
 }
 
 pub fn run() {
     let opts = Opts::from_args();
-    let entries = open(&mut stdin().lock(), &mut stdout().lock(), &opts.filename)
-        .expect("Failed loading entries.");
-    match opts.command {
-        Command::List => {
-            list(&mut stdout().lock(), entries);
+    if let Command::ClearClipboard { after, clipboard } = opts.command {
+        let mut password = String::new();
+        stdin().read_to_string(&mut password).expect("Failed reading the password.");
+        hold_and_clear_clipboard(clipboard, &password, time::Duration::from_secs(after));
+        return;
+    }
+    let socket = agent::socket_path();
+    if let Command::Lock = opts.command {
+        return lock(&socket);
+    }
+    if let Command::Agent { timeout, foreground: true } = opts.command {
+        run_agent(&socket, time::Duration::from_secs(timeout));
+        return;
+    }
+    // Making a key has nothing to do with the password file.
+    if let Command::Keygen { output } = opts.command {
+        return keygen(output);
+    }
+    let identities = match &opts.identity {
+        None => Vec::new(),
+        Some(identity) => match std::fs::read_to_string(identity) {
+            Err(e) => return eprintln!("Failed reading {}: {}", identity.display(), e),
+            Ok(text) => match pm::share::parse_identities(&text) {
+                Err(e) => return eprintln!("{}", e),
+                Ok(identities) => identities,
+            },
+        },
+    };
+    // `serve` reads the file again when something else changes it, so the
+    // passphrase is kept for that.
+    let remembered_passphrase = std::cell::RefCell::new(None);
+    let mut passphrase = || {
+        let passphrase = rpassword::prompt_password("Passphrase: ").map_err(|e| format!("Failed reading the passphrase: {}", e))?;
+        *remembered_passphrase.borrow_mut() = Some(passphrase.clone());
+        Ok(passphrase)
+    };
+    // git, or the command being run, is on the other end of stdin, so
+    // there's nobody to ask about creating the file.
+    let stdin_taken = matches!(opts.command, Command::Credential { .. } | Command::Exec { .. });
+    let opened = match agent::fetch(&socket, &opts.filename) {
+        Some(opened) => Ok(opened),
+        None if stdin_taken && !opts.filename.exists() => Err(format!("The file \"{}\" doesn't exist.", opts.filename.display())),
+        None => open(&mut stdin().lock(), &mut stdout().lock(), &opts.filename, &identities, &mut passphrase),
+    };
+    let (entries, vault) = match opened {
+        Err(e) => {
+            eprintln!("{}", e);
+            // exec exits with the command's exit code, so this can't look
+            // like it worked.
+            if let Command::Exec { .. } = opts.command {
+                std::process::exit(125);
+            }
+            return;
+        },
+        Ok(opened) => opened,
+    };
+    let mut session = Session{
+        filename: opts.filename,
+        vault,
+        entries,
+        identities,
+        passphrase: remembered_passphrase.into_inner(),
+    };
+    if let Command::Shell { autosave } = opts.command {
+        return shell(&mut session, autosave);
+    }
+    if let Command::Tui { timeout, clipboard } = opts.command {
+        return browse(&mut session, clipboard.unwrap_or_else(clipboards::Backend::detect), timeout);
+    }
+    let (entries, code) = execute(opts.command, &mut session);
+    if entries != session.entries {
+        save(&session.vault, &entries);
+    }
+    if code != 0 {
+        std::process::exit(code);
+    }
+}
+
+// What's open: the file, its entries, and what it took to open it.
+struct Session {
+    filename: std::path::PathBuf,
+    vault: pm::vault::Vault,
+    entries: pm::Entries,
+    identities: Vec<age::x25519::Identity>,
+    // The passphrase, if one was typed in, for `serve` to read the file again
+    // with.
+    passphrase: Option<String>,
+}
+
+// Runs a command on what's open. Returns the entries as they are afterward,
+// for the caller to save if they've changed, and the exit code.
+fn execute(command: Command, session: &mut Session) -> (pm::Entries, i32) {
+    let entries = session.entries.clone();
+    let vault = session.vault.clone();
+    let filename = session.filename.clone();
+    let identities = session.identities.clone();
+    let socket = agent::socket_path();
+    let mut new_entries = None;
+    let mut code = 0;
+    match command {
+        Command::List { in_folder, filter } => {
+            let mut filter = filter.filter();
+            if let Some(folder) = in_folder {
+                filter.folder = folder;
+            }
+            list(&mut stdout().lock(), entries, &filter);
         },
         Command::Add => {
-            add(&mut stdin().lock(), &mut stdout().lock(), entries)
-                .save(&opts.filename)
-                .expect("Error saving.");
+            new_entries = Some(add(&mut stdin().lock(), &mut stdout().lock(), entries));
         },
         Command::Show { entry: entry_name } => {
             match entries.getish(&entry_name) {
                 Err(e) => eprintln!("{}", e),
-                Ok(entry) => show(&mut stdin().lock(), &mut stdout().lock(), entries, &entry.name)
-                    .save(&opts.filename)
-                    .expect("Error saving."),
+                Ok(entry) => new_entries = Some(show(&mut stdin().lock(), &mut stdout().lock(), entries, &entry.name)),
             }
         },
-        Command::Edit { entry: entry_name } => {
+        Command::Edit { entry: entry_name, editor: false } => {
             match entries.getish(&entry_name) {
                 Err(e) => eprintln!("{}", e),
-                Ok(entry) => edit(&mut stdin().lock(), &mut stdout().lock(), entries, &entry.name)
-                    .save(&opts.filename)
-                    .expect("Error saving."),
+                Ok(entry) => new_entries = Some(edit(&mut stdin().lock(), &mut stdout().lock(), entries, &entry.name)),
+            }
+        },
+        Command::Edit { entry: entry_name, editor: true } => {
+            match (entries.getish(&entry_name), private_tmpfs()) {
+                (Err(e), _) | (_, Err(e)) => eprintln!("{}", e),
+                (Ok(entry), Ok(dir)) => {
+                    new_entries = Some(edit_in_editor(&mut stdin().lock(), &mut stdout().lock(), entries, &entry.name, &editor(), &dir));
+                },
             }
         },
         Command::Delete { entry: entry_name } => {
             match entries.getish(&entry_name) {
                 Err(e) => eprintln!("{}", e),
-                Ok(entry) => delete(&mut stdin().lock(), &mut stdout().lock(), entries, &entry.name)
-                    .save(&opts.filename)
-                    .expect("Error saving."),
+                Ok(entry) => new_entries = Some(delete(&mut stdin().lock(), &mut stdout().lock(), entries, &entry.name)),
             }
         },
-        Command::Clip { entry: entry_name } => {
-            match entries.getish(&entry_name) {
-                Err(e) => eprintln!("{}", e),
-                Ok(entry) => {
-                    clip(&mut stdin().lock(), &mut stdout().lock(), entries, &entry.name);
-                    println!("The password will be deleted out of your clipboard in 10 seconds.");
-                    thread::sleep(time::Duration::from_secs(10));
+        Command::Clip { entry: entry_name, field, sequence, timeout, clipboard } => {
+            let backend = clipboard.unwrap_or_else(clipboards::Backend::detect);
+            // A sequence ends with the password, so that's what gets cleared.
+            let field = if sequence { String::from("password") } else { field };
+            match (entries.getish(&entry_name), backend.open()) {
+                (Err(e), _) | (_, Err(e)) => eprintln!("{}", e),
+                (Ok(entry), Ok(mut board)) => match entry.field(&field) {
+                    None => eprintln!("\"{}\" doesn't have a field called \"{}\".", entry.name, field),
+                    Some(value) => {
+                        if sequence {
+                            clip_sequence(&mut stdin().lock(), &mut stdout().lock(), entries.clone(), &entry.name, &mut *board);
+                        } else {
+                            clip(&mut stdin().lock(), &mut stdout().lock(), entries.clone(), &entry.name, &field, &mut *board);
+                        }
+                        clear_clipboard_later(&filename, backend, value, timeout);
+                        println!("The {} will be deleted out of your clipboard in {} seconds.", field, timeout);
+                    },
                 },
             }
         },
+        Command::Keygen { output } => keygen(output),
+        Command::Lock => lock(&socket),
+        Command::ClearClipboard { .. } | Command::Agent { foreground: true, .. } => eprintln!("That's only for pm to run itself."),
+        Command::Shell { .. } => eprintln!("You're already in the shell."),
+        Command::Tui { .. } => eprintln!("That's only from the command line."),
+        Command::Agent { timeout, .. } => {
+            if agent::request(&socket, &agent::Request::Lock).is_some() {
+                println!("The agent that was running is locked.");
+            }
+            match start_agent(&filename, &vault, &entries, timeout) {
+                Err(e) => eprintln!("{}", e),
+                Ok(()) => println!("The agent has {} until it's unused for {} seconds or you run `pm lock`.", filename.display(), timeout),
+            }
+        },
         Command::Print { entry: entry_name } => {
             match entries.getish(&entry_name) {
                 Err(e) => eprintln!("{}", e),
                 Ok(entry) => println!("{}", entry.password),
             }
         },
+        Command::Mv { entry: entry_name, destination, force } => {
+            match entries.getish(&entry_name) {
+                Err(e) => eprintln!("{}", e),
+                Ok(entry) => new_entries = Some(mv(&mut stdout().lock(), entries, &entry.name, &destination, force)),
+            }
+        },
+        Command::Import { format, file, on_collision, dry_run, gpg } => {
+            let parsed = match format {
+                pm::import::Format::Pass => pm::pass::read(&file, &pm::pass::Crypt::Gpg(gpg)).map(pm::import::Imported::from),
+                _ => std::fs::read(&file)
+                    .map_err(|e| format!("Failed reading {}: {}", file.display(), e))
+                    .and_then(|bytes| pm::import::parse(format, &bytes)),
+            };
+            match parsed {
+                Err(e) => eprintln!("{}", e),
+                Ok(imported) => {
+                    let imported_entries = import(&mut stdin().lock(), &mut stdout().lock(), entries, imported, on_collision, dry_run);
+                    if !dry_run {
+                        new_entries = Some(imported_entries);
+                    }
+                },
+            }
+        },
+        Command::Export { format, destination, gpg, fields, no_secrets, filter } => {
+            let filter = filter.filter();
+            let selected: pm::Entries = entries.into_iter().filter(|(_, entry)| filter.matches(entry)).collect();
+            let exported = match (format, destination) {
+                (pm::export::Format::Pass, None) => Err(String::from("Give me the directory of the password store to export to.")),
+                (pm::export::Format::Pass, Some(_)) if !fields.is_empty() || no_secrets => {
+                    Err(String::from("A password store has everything in it, so --fields and --no-secrets don't work with pass."))
+                },
+                (pm::export::Format::Pass, Some(store)) => pm::pass::write(&store, &selected, &pm::pass::Crypt::Gpg(gpg))
+                    .map(|count| println!("Exported {} entries to {}.", count, store.display())),
+                (format, destination) => {
+                    let selected: Vec<pm::Entry> = selected.values().cloned().collect();
+                    let fields = if fields.is_empty() { None } else { Some(fields) };
+                    pm::export::columns(&selected, fields, no_secrets).and_then(|columns| {
+                        confirm_secrets(&mut stdin().lock(), &mut std::io::stderr(), &columns)?;
+                        match destination {
+                            None => pm::export::write(format, &mut stdout().lock(), &selected, &columns),
+                            Some(file) => pm::export::write(format, &mut pm::create_private(&file, false)?, &selected, &columns),
+                        }
+                    })
+                },
+            };
+            if let Err(e) = exported {
+                eprintln!("{}", e);
+            }
+        },
+        Command::Share { entries: selectors, to, output } => {
+            let shared = share(entries, &selectors, &to).and_then(|(bundle, count)| {
+                pm::create_private(&output, false)?
+                    .write_all(&bundle)
+                    .map_err(|e| format!("Failed writing {}: {}", output.display(), e))?;
+                Ok(count)
+            });
+            match shared {
+                Err(e) => eprintln!("{}", e),
+                Ok(count) => println!("Shared {} entries in {}.", count, output.display()),
+            }
+        },
+        Command::Receive { bundle, on_collision, dry_run } => {
+            let received = if identities.is_empty() {
+                Err(String::from("Give me the key the bundle was made for with --identity or PM_IDENTITY."))
+            } else {
+                std::fs::read(&bundle)
+                    .map_err(|e| format!("Failed reading {}: {}", bundle.display(), e))
+                    .and_then(|bundle| pm::share::open(&bundle, &identities))
+                    .map(pm::share::checked)
+            };
+            match received {
+                Err(e) => eprintln!("{}", e),
+                Ok(received) => {
+                    let received_entries = import(&mut stdin().lock(), &mut stdout().lock(), entries, received, on_collision, dry_run);
+                    if !dry_run {
+                        new_entries = Some(received_entries);
+                    }
+                },
+            }
+        },
+        Command::Recipients { command } => {
+            let changed = match command {
+                RecipientsCommand::List => {
+                    recipients(&mut stdout().lock(), &vault);
+                    return (entries, 0);
+                },
+                RecipientsCommand::Add { name, key: Some(key), passphrase: false } => {
+                    let key = if key.starts_with("age1") {
+                        Ok(key)
+                    } else {
+                        std::fs::read_to_string(&key)
+                            .map_err(|e| format!("\"{}\" isn't a public key, and I couldn't read it as a file: {}", key, e))
+                            .and_then(|text| pm::share::parse_recipients(&text))
+                            .and_then(|keys| match keys.as_slice() {
+                                [key] => Ok(key.to_string()),
+                                _ => Err(String::from("The file should have exactly one public key in it.")),
+                            })
+                    };
+                    key.and_then(|key| {
+                        // Encrypting a plain file to someone else's key would
+                        // lock us out of it.
+                        let ours = identities.iter().any(|identity| identity.to_public().to_string() == key.trim());
+                        if !vault.is_encrypted() && !ours {
+                            return Err(String::from("The first recipient has to be you. Give me your key file with --identity, or use --passphrase."));
+                        }
+                        vault.add_key(&name, &key)
+                    })
+                },
+                RecipientsCommand::Add { name, key: None, passphrase: true } => {
+                    rpassword::prompt_password(format!("Passphrase for {}: ", name))
+                        .and_then(|first| rpassword::prompt_password("Again: ").map(|second| (first, second)))
+                        .map_err(|e| format!("Failed reading the passphrase: {}", e))
+                        .and_then(|(first, second)| if first == second {
+                            vault.add_passphrase(&name, &first)
+                        } else {
+                            Err(String::from("The passphrases don't match."))
+                        })
+                },
+                RecipientsCommand::Add { .. } => Err(String::from("Give me either --key or --passphrase.")),
+                RecipientsCommand::Remove { name } => vault.remove(&name),
+            };
+            let changed = changed.and_then(|changed| changed.save(&entries).map(|_| changed));
+            if let Ok(changed) = &changed {
+                agent::update(&socket, changed, &entries);
+                session.vault = changed.clone();
+            }
+            match changed {
+                Err(e) => eprintln!("{}", e),
+                Ok(changed) if changed.recipients.len() < vault.recipients.len() => {
+                    println!("Encrypted {} again without them. If they kept a copy from before, change any passwords that matter.", filename.display());
+                },
+                Ok(_) if !vault.is_encrypted() => println!("{} is encrypted now.", filename.display()),
+                Ok(changed) => println!("{} recipients can open {} now.", changed.recipients.len(), filename.display()),
+            }
+        },
+        Command::Credential { action } => {
+            let updated = credential(&mut stdin().lock(), &mut stdout().lock(), entries.clone(), &action);
+            if updated != entries {
+                new_entries = Some(updated);
+            }
+        },
+        Command::Exec { env, command } => {
+            code = match exec::environment(&entries, &env) {
+                Err(e) => {
+                    eprintln!("{}", e);
+                    125
+                },
+                Ok(environment) => exec::run(&command, environment).unwrap_or_else(|(code, e)| {
+                    eprintln!("{}", e);
+                    code
+                }),
+            };
+        },
+        Command::Serve { socket, listen, token } => {
+            let listener = match (&socket, listen) {
+                (Some(socket), _) => server::listen_unix(socket).map(server::Listener::Unix),
+                (None, Some(address)) if !address.ip().is_loopback() => {
+                    Err(String::from("It's plain HTTP, so it only listens on loopback addresses, like 127.0.0.1."))
+                },
+                (None, Some(address)) => std::net::TcpListener::bind(address)
+                    .map(server::Listener::Tcp)
+                    .map_err(|e| format!("Failed listening on {}: {}", address, e)),
+                (None, None) => unreachable!("structopt requires one or the other."),
+            };
+            let listener = match listener {
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return (entries, 0);
+                },
+                Ok(listener) => listener,
+            };
+            let token = match (listen, token) {
+                (Some(_), None) => {
+                    let token = server::generate_token();
+                    eprintln!("Token: {}", token);
+                    Some(token)
+                },
+                (_, token) => token,
+            };
+            match (socket, listen) {
+                (Some(socket), _) => eprintln!("Listening on {}.", socket.display()),
+                (_, Some(address)) => eprintln!("Listening on http://{}.", address),
+                _ => {},
+            }
+            let path = vault.path.clone();
+            let mut reopen = || pm::vault::Vault::open(&path, &identities, &mut || {
+                session.passphrase.clone().ok_or_else(|| String::from("It needs a passphrase now. Start pm serve again."))
+            });
+            let state = server::State{modified: agent::modified(&vault.path), vault, entries};
+            server::serve(listener, state, token, &mut reopen);
+        },
+        Command::SshAdd { entry: entry_name, lifetime } => {
+            let added = entries.getish(&entry_name).and_then(|entry| {
+                let socket = std::env::var_os("SSH_AUTH_SOCK")
+                    .filter(|socket| !socket.is_empty())
+                    .ok_or_else(|| String::from("SSH_AUTH_SOCK isn't set. Is ssh-agent running?"))?;
+                let key = pm::ssh::private_key(&entry)?;
+                pm::ssh::add_to_agent(std::path::Path::new(&socket), &key, lifetime)?;
+                Ok(entry)
+            });
+            match (added, lifetime) {
+                (Err(e), _) => eprintln!("{}", e),
+                (Ok(entry), None) => println!("Added \"{}\" to ssh-agent.", entry.name),
+                (Ok(entry), Some(lifetime)) => println!("Added \"{}\" to ssh-agent for {} seconds.", entry.name, lifetime),
+            }
+        },
+        Command::SshKeygen { name, comment } => {
+            let comment = comment.unwrap_or_else(|| name.clone());
+            new_entries = Some(ssh_keygen(&mut stdout().lock(), entries, &name, &comment));
+        },
+        Command::Env { format, filter } => {
+            let filter = filter.filter();
+            let selected: Vec<pm::Entry> = entries.values().filter(|entry| filter.matches(entry)).cloned().collect();
+            match pm::environment::variables(&selected) {
+                Err(e) => {
+                    eprintln!("{}", e);
+                    code = 1;
+                },
+                Ok(variables) => stdout().write_all(pm::environment::format(format, &variables).as_bytes())
+                    .expect("Failed writing output. I can't imagine why this would happen."),
+            }
+        },
+        Command::Inject { input, output } => {
+            // Everything is filled in before anything is written, so a
+            // missing entry doesn't leave half a file behind.
+            let injected = std::fs::read_to_string(&input)
+                .map_err(|e| format!("Failed reading {}: {}", input.display(), e))
+                .and_then(|template| pm::template::render(&template, &entries))
+                .and_then(|rendered| match &output {
+                    None => stdout().write_all(rendered.as_bytes()).map_err(|e| format!("Failed writing the output: {}", e)),
+                    Some(output) => pm::create_private(output, false)?
+                        .write_all(rendered.as_bytes())
+                        .map_err(|e| format!("Failed writing {}: {}", output.display(), e)),
+                });
+            if let Err(e) = injected {
+                eprintln!("{}", e);
+                code = 1;
+            }
+        },
+        Command::Tags => {
+            tags(&mut stdout().lock(), entries);
+        },
+        Command::Tag { command } => {
+            let (tag, selectors, adding) = match command {
+                TagCommand::Add { tag, entries } => (tag, entries, true),
+                TagCommand::Remove { tag, entries } => (tag, entries, false),
+            };
+            if let Err(e) = pm::check_tag(&tag) {
+                eprintln!("{}", e);
+                return (entries, 0);
+            }
+            // Look everything up first so that a typo doesn't leave the tag
+            // on only some of the entries.
+            match selectors.iter().map(|selector| entries.getish(selector)).collect::<Result<Vec<_>, _>>() {
+                Err(e) => eprintln!("{}", e),
+                Ok(selected) => {
+                    let names: Vec<String> = selected.into_iter().map(|entry| entry.name).collect();
+                    let tagged = if adding {
+                        tag_add(&mut stdout().lock(), entries, &tag, &names)
+                    } else {
+                        tag_remove(&mut stdout().lock(), entries, &tag, &names)
+                    };
+                    new_entries = Some(tagged);
+                },
+            }
+        },
+    }
+    (new_entries.unwrap_or_else(|| session.entries.clone()), code)
+}
+
+// The commands the shell completes: the usual ones and its own, but not the
+// ones it won't run.
+const SHELL_COMMANDS: &[&str] = &[
+    "list", "add", "show", "edit", "delete", "clip", "print", "mv", "import", "export", "keygen", "share",
+    "receive", "lock", "recipients", "credential", "exec", "ssh-add", "ssh-keygen", "env", "inject",
+    "tags", "tag", "help", "save", "undo", "exit",
+];
+
+// Runs commands on the open file until `exit` or the end of input. Only the
+// command lines go in the history, and only in memory, so whatever is typed
+// at a prompt, like a password for `add`, is never kept.
+fn shell(session: &mut Session, autosave: bool) {
+    let config = rustyline::Config::builder().history_ignore_space(true).build();
+    let history = rustyline::history::MemHistory::with_config(&config);
+    let mut editor = match rustyline::Editor::with_history(config, history) {
+        Err(e) => return eprintln!("Failed starting the shell: {}", e),
+        Ok(editor) => editor,
+    };
+    editor.set_helper(Some(shell::Completions{
+        commands: SHELL_COMMANDS.iter().map(|command| String::from(*command)).collect(),
+        names: Vec::new(),
+    }));
+    let mut state = ShellState::new(session, autosave);
+    let prompt = format!("{}> ", session.filename.display());
+    let mut ended = false;
+    loop {
+        if let Some(completions) = editor.helper_mut() {
+            completions.names = session.entries.keys().cloned().collect();
+        }
+        let line = match editor.readline(&prompt) {
+            Err(rustyline::error::ReadlineError::Interrupted) => continue,
+            // The first end of input is like `exit`, which asks about unsaved
+            // changes. If there's nothing more after that, nobody's there to
+            // answer.
+            Err(rustyline::error::ReadlineError::Eof) if ended => return eprintln!("Leaving without saving."),
+            Err(rustyline::error::ReadlineError::Eof) => {
+                ended = true;
+                String::from("exit")
+            },
+            Err(e) => {
+                eprintln!("Failed reading the line: {}", e);
+                String::from("exit")
+            },
+            Ok(line) => {
+                ended = false;
+                line
+            },
+        };
+        let words = match shell::split_words(&line) {
+            Err(e) => {
+                eprintln!("{}", e);
+                continue;
+            },
+            Ok(words) => words,
+        };
+        if words.is_empty() {
+            continue;
+        }
+        // It's only a history, so there's no reason to stop over it.
+        let _ = editor.add_history_entry(line.as_str());
+        let builtin = state.builtin(&mut stdin().lock(), &mut stdout().lock(), session, &words);
+        match builtin {
+            Some(false) => return,
+            Some(true) => {},
+            None => state.command(session, &words),
+        }
+    }
+}
+
+// Why the shell won't run a command, if it won't.
+fn refusal(command: &Command) -> Option<&'static str> {
+    match command {
+        Command::Serve { .. } | Command::Tui { .. } => Some("That one takes over the terminal, so run it from the command line."),
+        // The agent hands every other pm what it was started with, which here
+        // could be changes that are never saved.
+        Command::Agent { .. } => Some("The agent would hand out changes the shell hasn't saved, so start it from the command line."),
+        _ => None,
+    }
+}
+
+// What the shell keeps track of besides what's open.
+struct ShellState {
+    autosave: bool,
+    // The entries as they are in the file.
+    saved: pm::Entries,
+    // When the file was last changed, to notice someone else changing it.
+    modified: Option<std::time::SystemTime>,
+    // The entries before each change, for `undo`.
+    undo: Vec<pm::Entries>,
+}
+
+impl ShellState {
+    fn new(session: &Session, autosave: bool) -> ShellState {
+        ShellState{
+            autosave,
+            saved: session.entries.clone(),
+            modified: agent::modified(&session.vault.path),
+            undo: Vec::new(),
+        }
+    }
+
+    // Handles the shell's own commands. It's None for anything else, and
+    // whether to keep going otherwise.
+    fn builtin(&mut self, reader: &mut impl std::io::BufRead, writer: &mut impl std::io::Write, session: &mut Session, words: &[String]) -> Option<bool> {
+        let words: Vec<&str> = words.iter().map(String::as_str).collect();
+        match words.as_slice() {
+            ["save"] => {
+                self.save(writer, session, false);
+            },
+            ["save", "--force"] => {
+                self.save(writer, session, true);
+            },
+            ["undo"] => {
+                match self.undo.pop() {
+                    None => writeln!(writer, "There's nothing to undo."),
+                    Some(entries) => {
+                        session.entries = entries;
+                        writeln!(writer, "Undone.")
+                    },
+                }.expect("Failed writing output. I can't imagine why this would happen.");
+                if self.autosave {
+                    self.save(writer, session, false);
+                }
+            },
+            ["exit"] | ["quit"] => {
+                if session.entries == self.saved {
+                    return Some(false);
+                }
+                let answer = readline(reader, writer, "There are unsaved changes. Save them? (y/n) ");
+                if answer == "y" {
+                    return Some(!self.save(writer, session, false));
+                }
+                return Some(answer != "n");
+            },
+            ["save", ..] | ["undo", ..] | ["exit", ..] | ["quit", ..] => {
+                writeln!(writer, "It's just `save`, `save --force`, `undo`, or `exit`.")
+                    .expect("Failed writing output. I can't imagine why this would happen.");
+            },
+            _ => return None,
+        }
+        writer.flush().expect("Couldn't flush stdout! I can't imagine why this would happen.");
+        Some(true)
+    }
+
+    // Runs one of the usual commands on the entries in memory.
+    fn command(&mut self, session: &mut Session, words: &[String]) {
+        let command = match ShellLine::clap().get_matches_from_safe(words) {
+            Err(e) => match e.kind {
+                structopt::clap::ErrorKind::HelpDisplayed | structopt::clap::ErrorKind::VersionDisplayed => return println!("{}", e.message),
+                _ => return eprintln!("{}", e.message),
+            },
+            Ok(matches) => ShellLine::from_clap(&matches).command,
+        };
+        if let Some(refusal) = refusal(&command) {
+            return eprintln!("{}", refusal);
+        }
+        let vault = session.vault.clone();
+        let (entries, _) = execute(command, session);
+        if session.vault != vault {
+            // Changing the recipients saved the file.
+            self.saved = session.entries.clone();
+            self.modified = agent::modified(&session.vault.path);
+        }
+        self.change(&mut stdout().lock(), session, entries);
+    }
+
+    fn change(&mut self, writer: &mut impl std::io::Write, session: &mut Session, entries: pm::Entries) {
+        if entries == session.entries {
+            return;
+        }
+        self.undo.push(std::mem::replace(&mut session.entries, entries));
+        if self.autosave {
+            self.save(writer, session, false);
+        }
+    }
+
+    // Writes the entries to the file, unless someone else changed it since it
+    // was opened or saved. Returns whether it did.
+    fn save(&mut self, writer: &mut impl std::io::Write, session: &Session, force: bool) -> bool {
+        let path = &session.vault.path;
+        let result = if session.entries == self.saved && agent::modified(path) == self.modified {
+            Ok("Nothing has changed.")
+        } else if agent::modified(path) != self.modified && !force {
+            Err(format!("{} changed since the shell read it. `save --force` writes over it anyway.", path.display()))
+        } else {
+            session.vault.save(&session.entries).map(|_| "Saved.")
+        };
+        let saved = match result {
+            Err(e) => {
+                writeln!(writer, "{}", e)
+                    .expect("Failed writing output. I can't imagine why this would happen.");
+                false
+            },
+            Ok(message) => {
+                agent::update(&agent::socket_path(), &session.vault, &session.entries);
+                self.saved = session.entries.clone();
+                self.modified = agent::modified(path);
+                writeln!(writer, "{}", message)
+                    .expect("Failed writing output. I can't imagine why this would happen.");
+                true
+            },
+        };
+        writer.flush().expect("Couldn't flush stdout! I can't imagine why this would happen.");
+        saved
+    }
+}
+
+// Shows the entries full screen until q. Adding, editing, and deleting leave
+// the screen for the usual prompts and come back when they're done, and each
+// change is saved right away, like it is for the commands.
+fn browse(session: &mut Session, backend: clipboards::Backend, timeout: u64) {
+    let mut terminal = match ratatui::try_init() {
+        Err(e) => return eprintln!("Failed setting up the terminal: {}", e),
+        Ok(terminal) => terminal,
+    };
+    let mut app = tui::App::default();
+    loop {
+        if let Err(e) = terminal.draw(|frame| tui::draw(frame, &app, &session.entries)) {
+            ratatui::restore();
+            return eprintln!("Failed drawing: {}", e);
+        }
+        let key = match ratatui::crossterm::event::read() {
+            Err(e) => {
+                ratatui::restore();
+                return eprintln!("Failed reading a key: {}", e);
+            },
+            Ok(ratatui::crossterm::event::Event::Key(key)) if key.kind == ratatui::crossterm::event::KeyEventKind::Press => key,
+            Ok(_) => continue,
+        };
+        let entries = session.entries.clone();
+        let changed = match app.key(key, &entries) {
+            tui::Action::Nothing => continue,
+            tui::Action::Quit => break,
+            tui::Action::Copy(name, field) => {
+                let mut output = Vec::new();
+                match (backend.open(), entries.get(&name).and_then(|entry| entry.field(field))) {
+                    (Err(e), _) => app.message = e,
+                    (_, None) => app.message = format!("\"{}\" doesn't have a {}.", name, field),
+                    (Ok(mut board), Some(value)) => {
+                        clip(&mut stdin().lock(), &mut output, entries.clone(), &name, field, &mut *board);
+                        clear_clipboard_later(&session.filename, backend, value, timeout);
+                        app.message = format!("{} It'll be deleted out of your clipboard in {} seconds.", String::from_utf8_lossy(&output).trim(), timeout);
+                    },
+                }
+                continue;
+            },
+            action => {
+                ratatui::restore();
+                let changed = match action {
+                    tui::Action::Add => add(&mut stdin().lock(), &mut stdout().lock(), entries.clone()),
+                    tui::Action::Edit(name) => edit(&mut stdin().lock(), &mut stdout().lock(), entries.clone(), &name),
+                    tui::Action::Delete(name) => delete(&mut stdin().lock(), &mut stdout().lock(), entries.clone(), &name),
+                    _ => unreachable!("The other actions are handled above."),
+                };
+                terminal = match ratatui::try_init() {
+                    Err(e) => return eprintln!("Failed setting up the terminal again: {}", e),
+                    Ok(terminal) => terminal,
+                };
+                changed
+            },
+        };
+        if changed != entries {
+            save(&session.vault, &changed);
+            session.entries = changed;
+            app.message = String::from("Saved.");
+            // Go to the entry that was added or edited. After a delete, stay
+            // where the entry was.
+            let touched = session.entries.values().find(|entry| entries.get(&entry.name) != Some(entry));
+            let names = app.names(&session.entries);
+            app.selected = touched
+                .and_then(|touched| names.iter().position(|name| *name == touched.name))
+                .unwrap_or(app.selected)
+                .min(names.len().saturating_sub(1));
+        }
+    }
+    ratatui::restore();
+}
+
+// Stops the agent.
+fn lock(socket: &std::path::Path) {
+    match agent::request(socket, &agent::Request::Lock) {
+        None => println!("There's no agent running."),
+        Some(_) => println!("Locked."),
+    }
+}
+
+// Makes a key for receiving shared entries.
+fn keygen(output: Option<std::path::PathBuf>) {
+    let (file, public) = pm::share::generate();
+    let written = match output {
+        None => stdout().write_all(file.as_bytes()).map_err(|e| format!("Failed writing the key: {}", e)),
+        Some(output) => pm::create_private(&output, true)
+            .and_then(|mut key| key.write_all(file.as_bytes()).map_err(|e| format!("Failed writing {}: {}", output.display(), e))),
+    };
+    match written {
+        Err(e) => eprintln!("{}", e),
+        Ok(()) => eprintln!("Public key: {}", public),
+    }
+}
+
+// Saves the entries and tells the agent, if it's running, about them.
+fn save(vault: &pm::vault::Vault, entries: &pm::Entries) {
+    vault.save(entries).expect("Error saving.");
+    agent::update(&agent::socket_path(), vault, entries);
+}
+
+// Starts `pm agent --foreground` in the background with what's already been
+// opened. Like the process that clears the clipboard, it gets the entries
+// through a pipe and has its own process group. This waits until it's
+// listening so that the next command can use it.
+#[allow(clippy::zombie_processes)]
+fn start_agent(filename: &std::path::Path, vault: &pm::vault::Vault, entries: &pm::Entries, timeout: u64) -> Result<(), String> {
+    let mut command = std::process::Command::new(std::env::current_exe().expect("Failed finding the pm executable."));
+    command.arg(filename)
+        .arg("agent")
+        .arg("--foreground")
+        .arg("--timeout").arg(timeout.to_string())
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::null());
+    command.process_group(0);
+    let mut child = command.spawn().map_err(|e| format!("Failed starting the agent: {}", e))?;
+    let put = agent::Request::Put{file: agent::canonical(filename), recipients: vault.recipients.clone(), entries: entries.clone()};
+    child.stdin.take().unwrap().write_all(serde_json::to_string(&put).expect("Failed serializing entries.").as_bytes())
+        .map_err(|e| format!("Failed passing the entries to the agent: {}", e))?;
+    for _ in 0..50 {
+        if agent::fetch(&agent::socket_path(), filename).is_some() {
+            return Ok(());
+        }
+        if let Ok(Some(_)) = child.try_wait() {
+            return Err(String::from("The agent stopped right away."));
+        }
+        thread::sleep(time::Duration::from_millis(100));
     }
+    Err(String::from("The agent didn't start listening."))
+}
+
+// What `pm agent --foreground` does. Memory is locked before the entries are
+// read in. Once it's locked or times out, the process exits, which is what
+// gets rid of the entries.
+fn run_agent(socket: &std::path::Path, idle: time::Duration) {
+    if let Err(e) = agent::protect_memory() {
+        eprintln!("{}", e);
+    }
+    let mut input = String::new();
+    stdin().read_to_string(&mut input).expect("Failed reading the entries.");
+    let (file, recipients, entries) = match serde_json::from_str(&input) {
+        Ok(agent::Request::Put { file, recipients, entries }) => (file, recipients, entries),
+        _ => return eprintln!("The agent needs the entries on stdin."),
+    };
+    let listener = match agent::listen(socket) {
+        Err(e) => return eprintln!("{}", e),
+        Ok(listener) => listener,
+    };
+    let modified = agent::modified(&file);
+    agent::serve(listener, agent::State{file, modified, recipients, entries}, idle);
+    let _ = std::fs::remove_file(socket);
 }
 
 // Prints a prompt for user input and returns the user input (leading and
@@ -114,34 +1205,58 @@ fn readline(reader: &mut impl std::io::BufRead, writer: &mut impl std::io::Write
         .expect("Failed writing output. I can't imagine why this would happen.");
     writer.flush().expect("Couldn't flush stdout! I can't imagine why this would happen.");
     let mut input = String::new();
-    reader.read_line(&mut input).ok().expect("Failed reading!");
+    reader.read_line(&mut input).expect("Failed reading!");
     input.trim().to_owned()
 }
 
 // Opens a password file, prompting to initialize one if the given file doesn't
-// exist.
-fn open<'a>(reader: &mut impl std::io::BufRead, writer: &mut impl std::io::Write, filename: &std::path::PathBuf) -> Result<pm::Entries, &'a str> {
+// exist. Entries from before IDs existed get one, and the file is saved right
+// away so that the IDs are the same the next time. An encrypted file is opened
+// with `identities` or, failing that, a passphrase.
+fn open(reader: &mut impl std::io::BufRead, writer: &mut impl std::io::Write, filename: &std::path::Path, identities: &[age::x25519::Identity], passphrase: &mut dyn FnMut() -> Result<String, String>) -> Result<(pm::Entries, pm::vault::Vault), String> {
     if filename.exists() {
-        Ok(pm::Entries::load(filename))
+        let (vault, entries) = pm::vault::Vault::open(filename, identities, passphrase)?;
+        let migrated = entries.assign_ids();
+        if migrated != entries {
+            vault.save(&migrated).map_err(|e| format!("The entries need IDs, but they couldn't be saved with them. {}", e))?;
+        }
+        Ok((migrated, vault))
     } else {
         let answer = readline(reader, writer, &format!(r###"The file "{}" doesn't exist. Create it? (y/n) "###, filename.to_str().expect("Failed to stringify filename.")));
         if answer != "y" {
-            Err("They apparently don't want to create a new file!")
+            Err(String::from("They apparently don't want to create a new file!"))
         } else {
             let new = pm::Entries::new();
-            new.save(filename).expect("Failed saving new, empty entries file.");
-            Ok(new)
+            new.save(filename).map_err(|e| format!("Failed creating {}: {}", filename.display(), e))?;
+            Ok((new, pm::vault::Vault::plain(filename)))
         }
     }
 }
 
-// Lists the entries for the user.
-fn list(writer: &mut impl std::io::Write, entries: pm::Entries) {
-    for (i, name) in entries.keys().enumerate() {
+// Lists the entries for the user. The ID is shown too since, unlike the index,
+// it's safe to use in scripts. Entries the filter leaves out keep their place
+// in the numbering so that the index still works with other commands.
+//
+// Entries in folders are shown as a tree. Names are sorted, so everything in a
+// folder comes one after the other and each folder only needs to be printed
+// once, the first time it shows up.
+fn list(writer: &mut impl std::io::Write, entries: pm::Entries, filter: &pm::Filter) {
+    let mut printed_folders: Vec<&str> = Vec::new();
+    for (i, (name, entry)) in entries.iter().enumerate() {
+        if !filter.matches(entry) {
+            continue;
+        }
+        let folders: Vec<&str> = pm::folder_of(name).split('/').filter(|f| !f.is_empty()).collect();
+        let shared = printed_folders.iter().zip(&folders).take_while(|(a, b)| a == b).count();
+        for (depth, folder) in folders.iter().enumerate().skip(shared) {
+            writeln!(writer, "{}{}/", "  ".repeat(depth), folder)
+                .expect("Failed writing output. I can't imagine why this would happen.");
+        }
         // + 1 because I don't want a 0 entry.
-        writeln!(writer, "{}: {}", i + 1, name)
+        writeln!(writer, "{}{}: {} [{}]", "  ".repeat(folders.len()), i + 1, pm::leaf_of(name), entry.id)
             .expect("Failed writing output. I can't imagine why this would happen.");
         writer.flush().expect("Couldn't flush stdout! I can't imagine why this would happen.");
+        printed_folders = folders;
     }
 }
 
@@ -150,8 +1265,12 @@ fn list(writer: &mut impl std::io::Write, entries: pm::Entries) {
 fn add(reader: &mut impl std::io::BufRead, writer: &mut impl std::io::Write, entries: pm::Entries) -> pm::Entries {
     let name = {
         loop {
-            let name = readline(reader, writer, "Name: ");
-            if entries.contains_key(&name) {
+            let name = readline(reader, writer, "Name (use slashes for folders, like work/email): ");
+            if let Err(e) = pm::check_name(&name) {
+                writeln!(writer, "{}", e)
+                    .expect("Failed writing output. I can't imagine why this would happen.");
+                writer.flush().expect("Couldn't flush stdout! I can't imagine why this would happen.");
+            } else if entries.contains_key(&name) {
                 writeln!(writer, "An entry with the name \"{}\" already exists. Did you want to edit it instead?", name)
                     .expect("Failed writing output. I can't imagine why this would happen.");
                 writer.flush().expect("Couldn't flush stdout! I can't imagine why this would happen.");
@@ -164,7 +1283,7 @@ fn add(reader: &mut impl std::io::BufRead, writer: &mut impl std::io::Write, ent
     let password = {
         loop {
             let password = readline(reader, writer, "Password (TODO; generate password, provide options, etc): ");
-            if password == "" {
+            if password.is_empty() {
                 // TODO; use generated password, or generate a new one and reprompt
             } else {
                 break password
@@ -172,7 +1291,9 @@ fn add(reader: &mut impl std::io::BufRead, writer: &mut impl std::io::Write, ent
         }
     };
     let notes = readline(reader, writer, "Notes: ");
-    entries.update(name.clone(), pm::Entry{name, username, password, notes})
+    let tags = pm::parse_tags(&readline(reader, writer, "Tags (separated by spaces): "));
+    let id = entries.new_id();
+    entries.update(name.clone(), pm::Entry{name, username, password, notes, id, tags, fields: pm::Fields::new()})
 }
 
 // Show an entry.
@@ -193,6 +1314,14 @@ fn show(_reader: &mut impl std::io::BufRead, writer: &mut impl std::io::Write, e
                 .expect("Failed writing output. I can't imagine why this would happen.");
             writeln!(writer, "Notes: {}", entry.notes)
                 .expect("Failed writing output. I can't imagine why this would happen.");
+            for (field, value) in &entry.fields {
+                writeln!(writer, "{}: {}", field, value)
+                    .expect("Failed writing output. I can't imagine why this would happen.");
+            }
+            writeln!(writer, "Tags: {}", join_tags(&entry.tags))
+                .expect("Failed writing output. I can't imagine why this would happen.");
+            writeln!(writer, "ID: {}", entry.id)
+                .expect("Failed writing output. I can't imagine why this would happen.");
             writer.flush().expect("Couldn't flush stdout! I can't imagine why this would happen.");
             entries
         },
@@ -211,16 +1340,22 @@ fn edit(reader: &mut impl std::io::BufRead, writer: &mut impl std::io::Write, en
         Some(entry) => {
             let original_name = &entry.name;
             let name = {
-                let given_name = readline(reader, writer, &format!("Name [{}]: ", &entry.name));
-                if given_name == "" {
-                    entry.name.to_owned()
-                } else {
-                    given_name
+                loop {
+                    let given_name = readline(reader, writer, &format!("Name [{}]: ", &entry.name));
+                    if given_name.is_empty() {
+                        break entry.name.to_owned();
+                    } else if let Err(e) = pm::check_name(&given_name) {
+                        writeln!(writer, "{}", e)
+                            .expect("Failed writing output. I can't imagine why this would happen.");
+                        writer.flush().expect("Couldn't flush stdout! I can't imagine why this would happen.");
+                    } else {
+                        break given_name;
+                    }
                 }
             };
             let username = {
                 let given_username = readline(reader, writer, &format!("Username [{}]: ", &entry.username));
-                if given_username == "" {
+                if given_username.is_empty() {
                     entry.username.to_owned()
                 } else {
                     given_username
@@ -228,7 +1363,7 @@ fn edit(reader: &mut impl std::io::BufRead, writer: &mut impl std::io::Write, en
             };
             let password = {
                 let given_password = readline(reader, writer, &format!("Password [{}]: ", &entry.password));
-                if given_password == "" {
+                if given_password.is_empty() {
                     entry.password.to_owned()
                 } else {
                     given_password
@@ -236,17 +1371,131 @@ fn edit(reader: &mut impl std::io::BufRead, writer: &mut impl std::io::Write, en
             };
             let notes = {
                 let given_notes = readline(reader, writer, &format!("Notes [{}]: ", &entry.notes));
-                if given_notes == "" {
+                if given_notes.is_empty() {
                     entry.notes.to_owned()
                 } else {
                     given_notes
                 }
             };
+            let tags = {
+                let given_tags = readline(reader, writer, &format!("Tags [{}]: ", join_tags(&entry.tags)));
+                if given_tags.is_empty() {
+                    entry.tags.clone()
+                } else {
+                    pm::parse_tags(&given_tags)
+                }
+            };
+            let id = entry.id.clone();
+            let fields = entry.fields.clone();
             // Remove the entry by the original name first because we're
             // editing the entry. If the name is changed, then we don't want to
             // leave the details under the old name.
             entries.without(original_name)
-                .update(name.clone(), pm::Entry{name, username, password, notes})
+                .update(name.clone(), pm::Entry{name, username, password, notes, id, tags, fields})
+        },
+    }
+}
+
+// Where `edit --editor` puts the entry while it's being edited: somewhere
+// that's only in memory, so the secrets in it never end up on a disk.
+// XDG_RUNTIME_DIR is a tmpfs of the user's own on most Linux systems, and
+// /dev/shm is the next best thing.
+fn private_tmpfs() -> Result<std::path::PathBuf, String> {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(std::path::PathBuf::from)
+        .into_iter()
+        .chain(Some(std::path::PathBuf::from("/dev/shm")))
+        .find(|dir| dir.is_dir())
+        .ok_or_else(|| String::from("There's no XDG_RUNTIME_DIR or /dev/shm to keep the entry in while it's edited, and I won't write it to the disk."))
+}
+
+// The editor, the same way git picks one.
+fn editor() -> String {
+    ["VISUAL", "EDITOR"].iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|editor| !editor.is_empty())
+        .unwrap_or_else(|| String::from("vi"))
+}
+
+// Deletes the file being edited however the editing ends, even in a panic.
+struct Scratch(std::path::PathBuf);
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+// Edits an entry all at once as a TOML document in an editor. It's read back
+// the same way the prompts would check it, then what changed is shown before
+// anything is. A mistake can be fixed by opening the editor again.
+fn edit_in_editor(reader: &mut impl std::io::BufRead, writer: &mut impl std::io::Write, entries: pm::Entries, name: &str, editor: &str, dir: &std::path::Path) -> pm::Entries {
+    let entry = match entries.get(name) {
+        None => {
+            writeln!(writer, "There's no entry with the name \"{}\".", name)
+                .expect("Failed writing output. I can't imagine why this would happen.");
+            writer.flush().expect("Couldn't flush stdout! I can't imagine why this would happen.");
+            return entries;
+        },
+        Some(entry) => entry.clone(),
+    };
+    let before = pm::document::to_toml(&entry);
+    let scratch = Scratch(dir.join(format!("pm-{}-{}.toml", std::process::id(), entry.id)));
+    let edited = pm::create_private(&scratch.0, true)
+        .and_then(|mut file| file.write_all(before.as_bytes()).map_err(|e| format!("Failed writing {}: {}", scratch.0.display(), e)))
+        .and_then(|_| loop {
+            // The editor can have arguments in it, like "code --wait".
+            let status = std::process::Command::new("sh")
+                .arg("-c")
+                .arg(format!("{} \"$1\"", editor))
+                .arg("sh")
+                .arg(&scratch.0)
+                .status()
+                .map_err(|e| format!("Failed running {}: {}", editor, e))?;
+            if !status.success() {
+                return Err(format!("{} quit with an error, so nothing changed.", editor));
+            }
+            let parsed = std::fs::read_to_string(&scratch.0)
+                .map_err(|e| format!("Failed reading {}: {}", scratch.0.display(), e))
+                .and_then(|text| pm::document::from_toml(&text, &entry, &entries));
+            match parsed {
+                Ok(edited) => break Ok(edited),
+                Err(e) => {
+                    writeln!(writer, "{}", e)
+                        .expect("Failed writing output. I can't imagine why this would happen.");
+                    if readline(reader, writer, "Open it again to fix it? (y/n) ") != "y" {
+                        return Err(String::from("Nothing changed."));
+                    }
+                },
+            }
+        });
+    drop(scratch);
+    let changes = edited.map(|edited| {
+        let changes = pm::document::changes(&entry, &edited);
+        (edited, changes)
+    });
+    let result = match changes {
+        Err(e) => Err(e),
+        Ok((_, changes)) if changes.is_empty() => Err(String::from("Nothing changed.")),
+        Ok((edited, changes)) => {
+            for line in changes {
+                writeln!(writer, "{}", line)
+                    .expect("Failed writing output. I can't imagine why this would happen.");
+            }
+            if readline(reader, writer, "Save these changes? (y/n) ") == "y" {
+                Ok(entries.without(&entry.name).update(edited.name.clone(), edited))
+            } else {
+                Err(String::from("Nothing changed."))
+            }
+        },
+    };
+    match result {
+        Ok(edited) => edited,
+        Err(e) => {
+            writeln!(writer, "{}", e)
+                .expect("Failed writing output. I can't imagine why this would happen.");
+            writer.flush().expect("Couldn't flush stdout! I can't imagine why this would happen.");
+            entries
         },
     }
 }
@@ -274,28 +1523,391 @@ fn delete(reader: &mut impl std::io::BufRead, writer: &mut impl std::io::Write,
     }
 }
 
-// Copies the password for an entry to the clipboard and also prints the
-// username as a reminder. (That happens to me sometimes when I can't user my
-// email address as a username.)
-fn clip(_reader: &mut impl std::io::BufRead, writer: &mut impl std::io::Write, entries: pm::Entries, name: &String) -> pm::Entries {
-    match entries.get(name) {
+// Copies a field of an entry to the clipboard. For the password, it also prints
+// the username as a reminder. (That happens to me sometimes when I can't user
+// my email address as a username.)
+fn clip(_reader: &mut impl std::io::BufRead, writer: &mut impl std::io::Write, entries: pm::Entries, name: &String, field: &str, board: &mut dyn Clipboard) -> pm::Entries {
+    match entries.get(name).map(|entry| (entry, entry.field(field))) {
         None => {
             writeln!(writer, "There's no entry with the name \"{}\".", name)
                 .expect("Failed writing output. I can't imagine why this would happen.");
-            writer.flush().expect("Couldn't flush stdout! I can't imagine why this would happen.");
         },
-        Some(entry) => {
-            let mut board: ClipboardContext = ClipboardProvider::new()
-                .expect("Failed getting access to the clipboard.");
-            board.set_contents(entry.password.clone()).expect("Failed setting clipboard contents.");
-            writeln!(writer, "Copied password for \"{}\". Your username is: {}", name, entry.username)
+        Some((_, None)) => {
+            writeln!(writer, "\"{}\" doesn't have a field called \"{}\".", name, field)
                 .expect("Failed writing output. I can't imagine why this would happen.");
-            writer.flush().expect("Couldn't flush stdout! I can't imagine why this would happen.");
+        },
+        Some((entry, Some(value))) => {
+            board.set(value).expect("Failed setting clipboard contents.");
+            if field == "password" {
+                writeln!(writer, "Copied password for \"{}\". Your username is: {}", name, entry.username)
+                    .expect("Failed writing output. I can't imagine why this would happen.");
+            } else {
+                writeln!(writer, "Copied {} for \"{}\".", field, name)
+                    .expect("Failed writing output. I can't imagine why this would happen.");
+            }
+        },
+    }
+    writer.flush().expect("Couldn't flush stdout! I can't imagine why this would happen.");
+    entries
+}
+
+// Copies the username and then the password, which is the order login forms
+// want them in. Some clipboards can tell when the username has been pasted;
+// for the rest, the user presses enter when they're ready for the password.
+fn clip_sequence(reader: &mut impl std::io::BufRead, writer: &mut impl std::io::Write, entries: pm::Entries, name: &String, board: &mut dyn Clipboard) -> pm::Entries {
+    match entries.get(name) {
+        None => {
+            writeln!(writer, "There's no entry with the name \"{}\".", name)
+                .expect("Failed writing output. I can't imagine why this would happen.");
+            writer.flush().expect("Couldn't flush stdout! I can't imagine why this would happen.");
+        },
+        Some(entry) => {
+            writeln!(writer, "Copied username for \"{}\". Paste it and the password will be copied next.", name)
+                .expect("Failed writing output. I can't imagine why this would happen.");
+            writer.flush().expect("Couldn't flush stdout! I can't imagine why this would happen.");
+            let waited = board.set_until_pasted(&entry.username).expect("Failed setting clipboard contents.");
+            if !waited {
+                readline(reader, writer, "Press enter once you've pasted it.");
+            }
+            board.set(&entry.password).expect("Failed setting clipboard contents.");
+            writeln!(writer, "Copied password for \"{}\".", name)
+                .expect("Failed writing output. I can't imagine why this would happen.");
+            writer.flush().expect("Couldn't flush stdout! I can't imagine why this would happen.");
         },
     }
     entries
 }
 
+// Renames an entry, keeping everything else about it. This is the same
+// `without`/`update` that `edit` does, without going through every prompt. A
+// destination ending in a slash is a folder to move the entry into, and "/" is
+// the top. An existing entry is only replaced if `force` is given.
+fn mv(writer: &mut impl std::io::Write, entries: pm::Entries, name: &str, destination: &str, force: bool) -> pm::Entries {
+    let new_name = if destination.ends_with('/') {
+        let folder = destination.trim_matches('/');
+        if folder.is_empty() {
+            pm::leaf_of(name).to_owned()
+        } else {
+            format!("{}/{}", folder, pm::leaf_of(name))
+        }
+    } else {
+        destination.to_owned()
+    };
+    let refusal = match (pm::check_name(&new_name), entries.get(name)) {
+        (Err(e), _) => Some(e),
+        (_, None) => Some(format!("There's no entry with the name \"{}\".", name)),
+        (_, Some(_)) if new_name == name => Some(format!("\"{}\" already has that name.", name)),
+        (_, Some(_)) if entries.contains_key(&new_name) && !force => Some(format!("An entry with the name \"{}\" already exists. Use --force to replace it.", new_name)),
+        _ => None,
+    };
+    match refusal {
+        Some(e) => {
+            writeln!(writer, "{}", e)
+                .expect("Failed writing output. I can't imagine why this would happen.");
+            writer.flush().expect("Couldn't flush stdout! I can't imagine why this would happen.");
+            entries
+        },
+        None => {
+            let entry = entries.get(name).unwrap().clone();
+            if entries.contains_key(&new_name) {
+                writeln!(writer, "Replaced \"{}\" with \"{}\".", new_name, name)
+                    .expect("Failed writing output. I can't imagine why this would happen.");
+            } else {
+                writeln!(writer, "Moved \"{}\" to \"{}\".", name, new_name)
+                    .expect("Failed writing output. I can't imagine why this would happen.");
+            }
+            writer.flush().expect("Couldn't flush stdout! I can't imagine why this would happen.");
+            entries.without(name).update(new_name.clone(), pm::Entry{name: new_name, ..entry})
+        },
+    }
+}
+
+// Makes an entry with a new SSH key in it and prints the public key, so that
+// it can go straight into authorized_keys.
+fn ssh_keygen(writer: &mut impl std::io::Write, entries: pm::Entries, name: &str, comment: &str) -> pm::Entries {
+    let generated = pm::check_name(name).and_then(|_| {
+        if entries.contains_key(name) {
+            Err(format!("An entry with the name \"{}\" already exists.", name))
+        } else {
+            pm::ssh::generate(comment)
+        }
+    });
+    match generated {
+        Err(e) => {
+            writeln!(writer, "{}", e)
+                .expect("Failed writing output. I can't imagine why this would happen.");
+            writer.flush().expect("Couldn't flush stdout! I can't imagine why this would happen.");
+            entries
+        },
+        Ok((private, public)) => {
+            writeln!(writer, "{}", public)
+                .expect("Failed writing output. I can't imagine why this would happen.");
+            writer.flush().expect("Couldn't flush stdout! I can't imagine why this would happen.");
+            let entry = pm::Entry{
+                name: name.to_owned(),
+                username: String::new(),
+                password: String::new(),
+                notes: String::new(),
+                id: entries.new_id(),
+                tags: pm::Tags::new(),
+                fields: pm::Fields::new()
+                    .update(String::from(pm::ssh::PRIVATE_KEY_FIELD), private)
+                    .update(String::from(pm::ssh::PUBLIC_KEY_FIELD), public),
+            };
+            entries.update(name.to_owned(), entry)
+        },
+    }
+}
+
+// Adds imported entries, sorting out the ones whose names are already taken
+// (including by something earlier in the same import). With `dry_run`, it only
+// says what it would do and returns the entries it was given.
+fn import(reader: &mut impl std::io::BufRead, writer: &mut impl std::io::Write, entries: pm::Entries, imported: pm::import::Imported, collision: pm::import::Collision, dry_run: bool) -> pm::Entries {
+    let would = if dry_run { "Would add" } else { "Adding" };
+    for reason in &imported.skipped {
+        writeln!(writer, "Skipping {}.", reason)
+            .expect("Failed writing output. I can't imagine why this would happen.");
+    }
+    let (mut added, mut skipped) = (0, imported.skipped.len());
+    let mut updated = entries.clone();
+    for entry in imported.entries {
+        let original_name = entry.name.clone();
+        let name = if !updated.contains_key(&original_name) {
+            Some(original_name.clone())
+        } else {
+            let suffixed = pm::import::suffixed(|name| updated.contains_key(name), &original_name);
+            match collision {
+                pm::import::Collision::Skip => None,
+                pm::import::Collision::Suffix => Some(suffixed),
+                pm::import::Collision::Prompt if dry_run => {
+                    writeln!(writer, "\"{}\" already exists. You'll be asked what to do.", original_name)
+                        .expect("Failed writing output. I can't imagine why this would happen.");
+                    continue;
+                },
+                // An empty answer skips, which is also what happens when
+                // stdin runs out.
+                pm::import::Collision::Prompt => loop {
+                    let answer = readline(reader, writer, &format!("\"{}\" already exists. Skip it, rename it to \"{}\", or overwrite it? (S/r/o) ", original_name, suffixed));
+                    match answer.as_str() {
+                        "" | "s" => break None,
+                        "r" => break Some(suffixed),
+                        "o" => break Some(original_name.clone()),
+                        _ => {},
+                    }
+                },
+            }
+        };
+        match name {
+            None => {
+                skipped += 1;
+                writeln!(writer, "Skipping \"{}\".", original_name)
+                    .expect("Failed writing output. I can't imagine why this would happen.");
+            },
+            Some(name) => {
+                added += 1;
+                if name == original_name {
+                    writeln!(writer, "{} \"{}\".", would, name)
+                        .expect("Failed writing output. I can't imagine why this would happen.");
+                } else {
+                    writeln!(writer, "{} \"{}\" as \"{}\".", would, original_name, name)
+                        .expect("Failed writing output. I can't imagine why this would happen.");
+                }
+                // An overwritten entry keeps its ID, so that anything
+                // picking it out by ID still gets it.
+                let id = match updated.get(&name) {
+                    Some(existing) => existing.id.clone(),
+                    None => updated.new_id(),
+                };
+                updated = updated.update(name.clone(), pm::Entry{name, id, ..entry});
+            },
+        }
+    }
+    let summary = match (dry_run, imported.merged) {
+        (true, 0) => format!("Would add {} and skip {}. Nothing was saved.", added, skipped),
+        (true, merged) => format!("Would add {}, merge {}, and skip {}. Nothing was saved.", added, merged, skipped),
+        (false, 0) => format!("Added {} and skipped {}.", added, skipped),
+        (false, merged) => format!("Added {}, merged {}, and skipped {}.", added, merged, skipped),
+    };
+    writeln!(writer, "{}", summary)
+        .expect("Failed writing output. I can't imagine why this would happen.");
+    writer.flush().expect("Couldn't flush stdout! I can't imagine why this would happen.");
+    if dry_run {
+        entries
+    } else {
+        updated
+    }
+}
+
+// Encrypts the selected entries into a bundle. Each of `to` is a public key or
+// a file of them. Returns the bundle and how many entries are in it.
+fn share(entries: pm::Entries, selectors: &[String], to: &[String]) -> Result<(Vec<u8>, usize), String> {
+    let mut selected: Vec<pm::Entry> = Vec::new();
+    for selector in selectors {
+        let entry = entries.getish(selector)?;
+        if !selected.contains(&entry) {
+            selected.push(entry);
+        }
+    }
+    let mut recipients = Vec::new();
+    for recipient in to {
+        let text = if recipient.starts_with("age1") {
+            recipient.clone()
+        } else {
+            std::fs::read_to_string(recipient).map_err(|e| format!("\"{}\" isn't a public key, and I couldn't read it as a file: {}", recipient, e))?
+        };
+        recipients.extend(pm::share::parse_recipients(&text)?);
+    }
+    if recipients.is_empty() {
+        return Err(String::from("There's nobody to share with."));
+    }
+    pm::share::seal(&selected, &recipients).map(|bundle| (bundle, selected.len()))
+}
+
+// Asks before exporting anything that might be a secret. The question goes to
+// `writer`, which shouldn't be stdout since that might be where the export is
+// going.
+fn confirm_secrets(reader: &mut impl std::io::BufRead, writer: &mut impl std::io::Write, columns: &[String]) -> Result<(), String> {
+    let secrets: Vec<&str> = columns.iter().map(String::as_str).filter(|column| pm::export::is_secret(column)).collect();
+    if secrets.is_empty() {
+        return Ok(());
+    }
+    writeln!(writer, "The export will have these in it, unencrypted: {}. Use --no-secrets to leave them out.", secrets.join(", "))
+        .expect("Failed writing output. I can't imagine why this would happen.");
+    match readline(reader, writer, "Export them anyway? (y/N) ").to_lowercase().as_str() {
+        "y" | "yes" => Ok(()),
+        _ => Err(String::from("Nothing was exported.")),
+    }
+}
+
+// Lists who can open the file.
+fn recipients(writer: &mut impl std::io::Write, vault: &pm::vault::Vault) {
+    if !vault.is_encrypted() {
+        writeln!(writer, "The file isn't encrypted. Add a recipient to encrypt it.")
+            .expect("Failed writing output. I can't imagine why this would happen.");
+    }
+    for recipient in &vault.recipients {
+        let how = if recipient.passphrase_key.is_empty() { "key" } else { "passphrase" };
+        writeln!(writer, "{} ({}): {}", recipient.name, how, recipient.public_key)
+            .expect("Failed writing output. I can't imagine why this would happen.");
+    }
+    writer.flush().expect("Couldn't flush stdout! I can't imagine why this would happen.");
+}
+
+// Answers git. It sends what it knows about the credential it wants as
+// key=value lines. For get, whatever is found is written back the same way;
+// for store and erase, nothing is. Actions this doesn't know about are
+// ignored, like git asks helpers to do.
+fn credential(reader: &mut impl std::io::BufRead, writer: &mut impl std::io::Write, entries: pm::Entries, action: &str) -> pm::Entries {
+    let mut text = String::new();
+    let mut line = String::new();
+    while reader.read_line(&mut line).expect("Failed reading from git.") > 0 && !line.trim_end().is_empty() {
+        text.push_str(&line);
+        line.clear();
+    }
+    let wanted = pm::credential::Credential::parse(&text);
+    match action {
+        "get" => {
+            if let Some(entry) = pm::credential::find(&entries, &wanted) {
+                if !entry.password.is_empty() {
+                    let found = pm::credential::Credential{username: entry.username, password: entry.password, ..wanted};
+                    writer.write_all(found.format().as_bytes())
+                        .expect("Failed writing output. I can't imagine why this would happen.");
+                    writer.flush().expect("Couldn't flush stdout! I can't imagine why this would happen.");
+                }
+            }
+            entries
+        },
+        "store" => pm::credential::store(entries, &wanted),
+        "erase" => pm::credential::erase(entries, &wanted),
+        _ => entries,
+    }
+}
+
+// Lists every tag and how many entries have it.
+fn tags(writer: &mut impl std::io::Write, entries: pm::Entries) {
+    for (tag, count) in entries.tag_counts() {
+        writeln!(writer, "{}: {}", tag, count)
+            .expect("Failed writing output. I can't imagine why this would happen.");
+    }
+    writer.flush().expect("Couldn't flush stdout! I can't imagine why this would happen.");
+}
+
+// Adds a tag to each of the named entries.
+fn tag_add(writer: &mut impl std::io::Write, entries: pm::Entries, tag: &str, names: &[String]) -> pm::Entries {
+    names.iter().fold(entries, |entries, name| {
+        let entry = entries.get(name).expect("The caller should have checked the name.").clone();
+        writeln!(writer, "Tagged \"{}\" with \"{}\".", name, tag)
+            .expect("Failed writing output. I can't imagine why this would happen.");
+        let tags = entry.tags.update(tag.to_owned());
+        entries.update(name.clone(), pm::Entry{tags, ..entry})
+    })
+}
+
+// Removes a tag from each of the named entries.
+fn tag_remove(writer: &mut impl std::io::Write, entries: pm::Entries, tag: &str, names: &[String]) -> pm::Entries {
+    names.iter().fold(entries, |entries, name| {
+        let entry = entries.get(name).expect("The caller should have checked the name.").clone();
+        writeln!(writer, "Removed \"{}\" from \"{}\".", tag, name)
+            .expect("Failed writing output. I can't imagine why this would happen.");
+        let tags = entry.tags.without(tag);
+        entries.update(name.clone(), pm::Entry{tags, ..entry})
+    })
+}
+
+// Formats tags the same way they're typed in.
+fn join_tags(tags: &pm::Tags) -> String {
+    tags.iter().cloned().collect::<Vec<_>>().join(" ")
+}
+
+// Starts `pm clear-clipboard` in the background so that the shell isn't
+// blocked while we wait to clear the clipboard. The password goes through a
+// pipe instead of the arguments so that it doesn't show up in `ps`. It's put
+// in its own process group so that a ^C at the prompt doesn't kill it. It's
+// never waited on; pm exits right after, and init adopts it.
+#[allow(clippy::zombie_processes)]
+fn clear_clipboard_later(filename: &std::path::Path, backend: clipboards::Backend, password: &str, timeout: u64) {
+    let mut command = std::process::Command::new(std::env::current_exe().expect("Failed finding the pm executable."));
+    command.arg(filename)
+        .arg("clear-clipboard")
+        .arg("--after").arg(timeout.to_string())
+        .arg("--clipboard").arg(backend.to_string())
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null());
+    command.process_group(0);
+    let mut child = command.spawn().expect("Failed starting the process that clears the clipboard.");
+    child.stdin.take().unwrap().write_all(password.as_bytes())
+        .expect("Failed passing the password to the process that clears the clipboard.");
+}
+
+// Clears the clipboard after a while, but only if it still has the password in
+// it. If the user copied something else since, that's left alone.
+//
+// The X11 clipboard's contents go away with the program that set them, so for
+// that one this sets them again to hold onto them until it's time to clear.
+fn hold_and_clear_clipboard(backend: clipboards::Backend, password: &str, after: time::Duration) {
+    let mut board = backend.open().expect("Failed getting access to the clipboard.");
+    hold_and_clear(&mut *board, backend.holds_contents(), password, after);
+}
+
+fn hold_and_clear(board: &mut dyn Clipboard, hold: bool, password: &str, after: time::Duration) {
+    if hold {
+        board.set(password).expect("Failed setting clipboard contents.");
+    }
+    thread::sleep(after);
+    clear_if_unchanged(board, password);
+}
+
+// Clears the clipboard if it still has the password in it. Clipboards that
+// can't be read are cleared anyway; wiping something the user copied is better
+// than leaving a password behind.
+fn clear_if_unchanged(board: &mut dyn Clipboard, password: &str) {
+    let unchanged = board.get().map_or(true, |contents| contents == password);
+    if unchanged {
+        board.clear().expect("Failed clearing the clipboard.");
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -309,6 +1921,10 @@ mod tests {
         assert_eq!(readline(&mut reader, &mut writer, "any prompt"), "y");
     }
 
+    fn no_passphrase() -> Result<String, String> {
+        Err(S("Nobody should have asked."))
+    }
+
     #[test]
     fn test_open() {
         let mut reader = &(b"y\n")[..];
@@ -322,10 +1938,10 @@ mod tests {
                 .to_owned()
         };
         let path = std::path::PathBuf::from(&filename);
-        assert_eq!(path.exists(), false);
+        assert!(!path.exists());
 
         // A new file will be created with empty entries.
-        let new_entries = open(&mut reader, &mut writer, &path).unwrap();
+        let new_entries = open(&mut reader, &mut writer, &path, &[], &mut no_passphrase).unwrap().0;
         assert_eq!(new_entries.serialize(), "{}");
 
         // Save a new entry.
@@ -334,13 +1950,44 @@ mod tests {
             username: S("new username"),
             password: S("new password"),
             notes: S("new notes"),
+            id: S("new"),
+            tags: pm::Tags::new(),
+            fields: pm::Fields::new(),
         }).save(&path).unwrap();
 
+        // A file that can't be made isn't a panic.
+        let mut reader = &(b"y\n")[..];
+        let nowhere = path.with_extension("d").join("pm.json");
+        assert!(open(&mut reader, &mut writer, &nowhere, &[], &mut no_passphrase).unwrap_err().starts_with("Failed creating "));
+
         // Re-open the newly saved file.
         let mut reader = &(b"y\n")[..];
         let mut writer: Vec<u8> = Vec::new();
-        let updated_entries = open(&mut reader, &mut writer, &path).unwrap();
-        assert_eq!(updated_entries.serialize(), r###"{"new":{"name":"new","username":"new username","password":"new password","notes":"new notes"}}"###);
+        let updated_entries = open(&mut reader, &mut writer, &path, &[], &mut no_passphrase).unwrap().0;
+        assert_eq!(updated_entries.serialize(), r###"{"new":{"name":"new","username":"new username","password":"new password","notes":"new notes","id":"new"}}"###);
+
+        // A file from before entries had IDs is given them, and they're saved
+        // so they're the same the next time it's opened.
+        std::fs::write(&path, r###"{"old":{"name":"old","username":"","password":"","notes":""}}"###).unwrap();
+        let migrated_entries = open(&mut reader, &mut writer, &path, &[], &mut no_passphrase).unwrap().0;
+        let id = migrated_entries.get("old").unwrap().id.clone();
+        assert_eq!(id.len(), 8);
+        assert_eq!(pm::Entries::load(&path), migrated_entries);
+        assert_eq!(open(&mut reader, &mut writer, &path, &[], &mut no_passphrase).unwrap().0.get("old").unwrap().id, id);
+
+        // An encrypted file needs the key, and IDs are saved into it the same
+        // way.
+        let identity = age::x25519::Identity::generate();
+        let vault = pm::vault::Vault::plain(&path).add_key("me", &identity.to_public().to_string()).unwrap();
+        vault.save(&pm::Entries::deserialize(r###"{"old":{"name":"old","username":"","password":"","notes":""}}"###)).unwrap();
+        assert!(open(&mut reader, &mut writer, &path, &[], &mut no_passphrase).is_err());
+        let (encrypted_entries, opened) = open(&mut reader, &mut writer, &path, std::slice::from_ref(&identity), &mut no_passphrase).unwrap();
+        assert_eq!(opened, vault);
+        let id = encrypted_entries.get("old").unwrap().id.clone();
+        assert_eq!(id.len(), 8);
+        assert_eq!(open(&mut reader, &mut writer, &path, &[identity], &mut no_passphrase).unwrap().0.get("old").unwrap().id, id);
+
+        std::fs::remove_file(&path).unwrap();
     }
 
     #[test]
@@ -352,6 +1999,9 @@ mod tests {
                 username: S("one username"),
                 password: S("one password"),
                 notes: S("one notes"),
+                id: S("1"),
+                tags: pm::parse_tags("work prod"),
+                fields: pm::Fields::new(),
             },
         ).update(
             S("two"),
@@ -360,17 +2010,69 @@ mod tests {
                 username: S("two username"),
                 password: S("two password"),
                 notes: S("two notes"),
+                id: S("2"),
+                tags: pm::parse_tags("home"),
+                fields: pm::Fields::new(),
             },
         );
 
         let mut writer = Vec::new();
-        list(&mut writer, entries);
-        assert_eq!(std::str::from_utf8(&writer), Ok("1: one\n2: two\n"));
+        list(&mut writer, entries.clone(), &pm::Filter::default());
+        assert_eq!(std::str::from_utf8(&writer), Ok("1: one [1]\n2: two [2]\n"));
+
+        // Filtered entries keep their index.
+        let mut writer = Vec::new();
+        list(&mut writer, entries.clone(), &pm::Filter{folder: S(""), all_tags: vec![S("home")], any_tags: vec![], search: S("")});
+        assert_eq!(std::str::from_utf8(&writer), Ok("2: two [2]\n"));
+
+        let mut writer = Vec::new();
+        list(&mut writer, entries.clone(), &pm::Filter{folder: S(""), all_tags: vec![S("work"), S("home")], any_tags: vec![], search: S("")});
+        assert_eq!(std::str::from_utf8(&writer), Ok(""));
+
+        let mut writer = Vec::new();
+        list(&mut writer, entries.clone(), &pm::Filter{folder: S(""), all_tags: vec![], any_tags: vec![S("prod"), S("home")], search: S("")});
+        assert_eq!(std::str::from_utf8(&writer), Ok("1: one [1]\n2: two [2]\n"));
+    }
+
+    #[test]
+    fn test_list_tree() {
+        let entries = ["work/aws/prod-root", "work/aws/dev-root", "work/email", "bank", "home/wifi"].iter()
+            .fold(pm::Entries::new(), |entries, name| entries.update(S(name), pm::Entry{
+                name: S(name),
+                username: S("username"),
+                password: S("password"),
+                notes: S("notes"),
+                id: S(pm::leaf_of(name)),
+                tags: pm::Tags::new(),
+                fields: pm::Fields::new(),
+            }));
+
+        let mut writer = Vec::new();
+        list(&mut writer, entries.clone(), &pm::Filter::default());
+        assert_eq!(std::str::from_utf8(&writer), Ok(concat!(
+            "1: bank [bank]\n",
+            "home/\n",
+            "  2: wifi [wifi]\n",
+            "work/\n",
+            "  aws/\n",
+            "    3: dev-root [dev-root]\n",
+            "    4: prod-root [prod-root]\n",
+            "  5: email [email]\n",
+        )));
+
+        let mut writer = Vec::new();
+        list(&mut writer, entries.clone(), &pm::Filter{folder: S("work/aws"), all_tags: vec![], any_tags: vec![], search: S("")});
+        assert_eq!(std::str::from_utf8(&writer), Ok(concat!(
+            "work/\n",
+            "  aws/\n",
+            "    3: dev-root [dev-root]\n",
+            "    4: prod-root [prod-root]\n",
+        )));
     }
 
     #[test]
     fn test_add() {
-        let mut reader = &(b"myname\nmyusername\nmypassword\nmynotes\n")[..];
+        let mut reader = &(b"myname\nmyusername\nmypassword\nmynotes\n\n")[..];
         let mut writer: Vec<u8> = Vec::new();
         let entries = add(&mut reader, &mut writer, pm::Entries::new());
         let expected_entries = pm::Entries::new().update(S("myname"), pm::Entry{
@@ -378,31 +2080,43 @@ mod tests {
             username: S("myusername"),
             password: S("mypassword"),
             notes: S("mynotes"),
+            // The ID is random, so I can only check that one was given.
+            id: entries.get("myname").unwrap().id.clone(),
+            tags: pm::Tags::new(),
+            fields: pm::Fields::new(),
         });
         assert_eq!(entries, expected_entries);
+        assert_eq!(entries.get("myname").unwrap().id.len(), 8);
 
         // Start off repeating "myname" so that I can check that uniquess is
         // enforced.
-        let mut reader = &(b"myname\nmysecondname\nmysecondusername\nmysecondpassword\nmysecondnotes\n")[..];
+        let mut reader = &(b"myname\nmysecondname\nmysecondusername\nmysecondpassword\nmysecondnotes\nwork  prod\n")[..];
         let new_entries = add(&mut reader, &mut writer, entries);
         let expected_new_entries = expected_entries.update(S("mysecondname"), pm::Entry{
             name: S("mysecondname"),
             username: S("mysecondusername"),
             password: S("mysecondpassword"),
             notes: S("mysecondnotes"),
+            id: new_entries.get("mysecondname").unwrap().id.clone(),
+            tags: pm::parse_tags("prod work"),
+            fields: pm::Fields::new(),
         });
         assert_eq!(new_entries, expected_new_entries);
+        assert_ne!(new_entries.get("mysecondname").unwrap().id, new_entries.get("myname").unwrap().id);
     }
 
     #[test]
     fn test_edit() {
-        let mut reader = &(b"\n\n\n\n")[..];
+        let mut reader = &(b"\n\n\n\n\n")[..];
         let mut writer: Vec<u8> = Vec::new();
         let original_entries = pm::Entries::new().update(S("myname"), pm::Entry{
             name: S("myname"),
             username: S("myusername"),
             password: S("mypassword"),
             notes: S("mynotes"),
+            id: S("myid"),
+            tags: pm::Tags::new(),
+            fields: pm::Fields::new(),
         });
 
         // This first update edits and entry but makes no changes by using the
@@ -411,23 +2125,29 @@ mod tests {
         assert_eq!(first_update, original_entries);
 
         // Edits without changing the name.
-        let mut reader = &(b"\nnewusername\nnewpassword\nnewnotes\n")[..];
+        let mut reader = &(b"\nnewusername\nnewpassword\nnewnotes\n\n")[..];
         let second_update = edit(&mut reader, &mut writer, first_update.clone(), &S("myname"));
         assert_eq!(second_update, pm::Entries::new().update(S("myname"), pm::Entry{
             name: S("myname"),
             username: S("newusername"),
             password: S("newpassword"),
             notes: S("newnotes"),
+            id: S("myid"),
+            tags: pm::Tags::new(),
+            fields: pm::Fields::new(),
         }));
 
         // Update the name and ensure the entry under the old name is deleted.
-        let mut reader = &(b"newname\nnewusername\nnewpassword\nnewnotes\n")[..];
+        let mut reader = &(b"newname\nnewusername\nnewpassword\nnewnotes\nnewtag\n")[..];
         let second_update = edit(&mut reader, &mut writer, first_update.clone(), &S("myname"));
         assert_eq!(second_update, pm::Entries::new().update(S("newname"), pm::Entry{
             name: S("newname"),
             username: S("newusername"),
             password: S("newpassword"),
             notes: S("newnotes"),
+            id: S("myid"),
+            tags: pm::parse_tags("newtag"),
+            fields: pm::Fields::new(),
         }));
     }
 
@@ -440,6 +2160,9 @@ mod tests {
             username: S("exists username"),
             password: S("exists password"),
             notes: S("exists notes"),
+            id: S("exists"),
+            tags: pm::Tags::new(),
+            fields: pm::Fields::new(),
         });
 
         let wrong_name_entries = delete(&mut reader, &mut writer, entries.clone(), &S("doesn't exist"));
@@ -463,11 +2186,573 @@ mod tests {
             username: S("myusername"),
             password: S("mypassword"),
             notes: S("mynotes"),
+            id: S("myid"),
+            tags: pm::Tags::new(),
+            fields: pm::Fields::new().update(S("pin"), S("1234")),
         });
 
-        let returned_entries = clip(&mut reader, &mut writer, entries.clone(), &S("myname"));
-        let mut board: ClipboardContext = ClipboardProvider::new().unwrap();
+        let mut board = clipboards::Fake::default();
+        let returned_entries = clip(&mut reader, &mut writer, entries.clone(), &S("myname"), "password", &mut board);
         assert_eq!(returned_entries, entries);
-        assert_eq!(board.get_contents().unwrap(), "mypassword");
+        assert_eq!(board.contents, "mypassword");
+
+        clip(&mut reader, &mut writer, entries.clone(), &S("myname"), "username", &mut board);
+        assert_eq!(board.contents, "myusername");
+        clip(&mut reader, &mut writer, entries.clone(), &S("myname"), "pin", &mut board);
+        assert_eq!(board.contents, "1234");
+
+        // A field that isn't there doesn't change anything.
+        clip(&mut reader, &mut writer, entries.clone(), &S("myname"), "nope", &mut board);
+        assert_eq!(board.contents, "1234");
+
+        board.set("mypassword").unwrap();
+        // It's only cleared if it still has the password in it.
+        clear_if_unchanged(&mut board, "mypassword");
+        assert_eq!(board.contents, "");
+        board.contents = S("something else");
+        clear_if_unchanged(&mut board, "mypassword");
+        assert_eq!(board.contents, "something else");
+    }
+
+    #[test]
+    fn test_hold_and_clear() {
+        // Nothing else was copied, so the password is cleared.
+        let mut board = clipboards::Fake{contents: S("mypassword")};
+        hold_and_clear(&mut board, false, "mypassword", time::Duration::from_millis(0));
+        assert_eq!(board.contents, "");
+
+        // Something else was copied, so it's left alone.
+        let mut board = clipboards::Fake{contents: S("something else")};
+        hold_and_clear(&mut board, false, "mypassword", time::Duration::from_millis(0));
+        assert_eq!(board.contents, "something else");
+
+        // A clipboard that holds contents gets the password again first.
+        let mut board = clipboards::Fake{contents: S("")};
+        hold_and_clear(&mut board, true, "mypassword", time::Duration::from_millis(0));
+        assert_eq!(board.contents, "");
+    }
+
+    #[test]
+    fn test_clip_sequence() {
+        let mut reader = &(b"\n")[..];
+        let mut writer: Vec<u8> = Vec::new();
+        let entries = pm::Entries::new().update(S("myname"), pm::Entry{
+            name: S("myname"),
+            username: S("myusername"),
+            password: S("mypassword"),
+            notes: S("mynotes"),
+            id: S("myid"),
+            tags: pm::Tags::new(),
+            fields: pm::Fields::new(),
+        });
+
+        // The fake clipboard can't tell when it's pasted, so it waits for
+        // enter before copying the password.
+        let mut board = clipboards::Fake::default();
+        let returned_entries = clip_sequence(&mut reader, &mut writer, entries.clone(), &S("myname"), &mut board);
+        assert_eq!(returned_entries, entries);
+        assert_eq!(board.contents, "mypassword");
+        assert_eq!(reader.len(), 0);
+        assert!(std::str::from_utf8(&writer).unwrap().contains("Press enter once you've pasted it."));
+    }
+
+    #[test]
+    fn test_tags() {
+        let entries = pm::Entries::new().update(S("one"), pm::Entry{
+            name: S("one"),
+            username: S("one username"),
+            password: S("one password"),
+            notes: S("one notes"),
+            id: S("1"),
+            tags: pm::parse_tags("work prod"),
+            fields: pm::Fields::new(),
+        }).update(S("two"), pm::Entry{
+            name: S("two"),
+            username: S("two username"),
+            password: S("two password"),
+            notes: S("two notes"),
+            id: S("2"),
+            tags: pm::parse_tags("work"),
+            fields: pm::Fields::new(),
+        });
+
+        let mut writer = Vec::new();
+        tags(&mut writer, entries);
+        assert_eq!(std::str::from_utf8(&writer), Ok("prod: 1\nwork: 2\n"));
+    }
+
+    fn shell_session(path: &std::path::Path) -> Session {
+        let vault = pm::vault::Vault::plain(path);
+        vault.save(&pm::Entries::new()).unwrap();
+        Session{filename: path.to_owned(), vault, entries: pm::Entries::new(), identities: Vec::new(), passphrase: None}
+    }
+
+    fn with_entry(entries: &pm::Entries, name: &str) -> pm::Entries {
+        entries.update(name.to_owned(), pm::Entry{name: name.to_owned(), ..pm::Entry::default()})
+    }
+
+    fn builtin(state: &mut ShellState, session: &mut Session, line: &str, input: &str) -> (Option<bool>, String) {
+        let mut writer = Vec::new();
+        let words = shell::split_words(line).unwrap();
+        let result = state.builtin(&mut input.as_bytes(), &mut writer, session, &words);
+        (result, String::from_utf8(writer).unwrap())
+    }
+
+    #[test]
+    fn test_shell_undo() {
+        let temp = mktemp::Temp::new_dir().unwrap();
+        let mut session = shell_session(&temp.join("pm.json"));
+        let mut state = ShellState::new(&session, false);
+        let one = with_entry(&session.entries, "one");
+        let two = with_entry(&one, "two");
+        state.change(&mut Vec::new(), &mut session, one.clone());
+        state.change(&mut Vec::new(), &mut session, two.clone());
+        state.change(&mut Vec::new(), &mut session, two.clone());
+        assert_eq!(session.entries, two);
+        assert_eq!(builtin(&mut state, &mut session, "undo", ""), (Some(true), S("Undone.\n")));
+        assert_eq!(session.entries, one);
+        assert_eq!(builtin(&mut state, &mut session, "undo", ""), (Some(true), S("Undone.\n")));
+        assert_eq!(session.entries, pm::Entries::new());
+        assert_eq!(builtin(&mut state, &mut session, "undo", ""), (Some(true), S("There's nothing to undo.\n")));
+        // Nothing was saved.
+        assert_eq!(std::fs::read_to_string(temp.join("pm.json")).unwrap(), "{}");
+    }
+
+    #[test]
+    fn test_shell_save() {
+        let temp = mktemp::Temp::new_dir().unwrap();
+        let path = temp.join("pm.json");
+        let mut session = shell_session(&path);
+        let mut state = ShellState::new(&session, false);
+        assert_eq!(builtin(&mut state, &mut session, "save", ""), (Some(true), S("Nothing has changed.\n")));
+        let changed = with_entry(&session.entries, "one");
+        state.change(&mut Vec::new(), &mut session, changed);
+        assert_eq!(builtin(&mut state, &mut session, "save", ""), (Some(true), S("Saved.\n")));
+        assert!(std::fs::read_to_string(&path).unwrap().contains("\"one\""));
+
+        // Someone else changed the file.
+        let changed = with_entry(&session.entries, "two");
+        state.change(&mut Vec::new(), &mut session, changed);
+        let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_modified(std::time::SystemTime::now() + std::time::Duration::from_secs(60)).unwrap();
+        let (result, output) = builtin(&mut state, &mut session, "save", "");
+        assert_eq!(result, Some(true));
+        assert!(output.ends_with("changed since the shell read it. `save --force` writes over it anyway.\n"));
+        assert!(!std::fs::read_to_string(&path).unwrap().contains("\"two\""));
+        assert_eq!(builtin(&mut state, &mut session, "save --force", ""), (Some(true), S("Saved.\n")));
+        assert!(std::fs::read_to_string(&path).unwrap().contains("\"two\""));
+        assert_eq!(builtin(&mut state, &mut session, "save now", "").0, Some(true));
+        assert_eq!(builtin(&mut state, &mut session, "list", ""), (None, S("")));
+    }
+
+    #[test]
+    fn test_shell_autosave() {
+        let temp = mktemp::Temp::new_dir().unwrap();
+        let path = temp.join("pm.json");
+        let mut session = shell_session(&path);
+        let mut state = ShellState::new(&session, true);
+        let mut writer = Vec::new();
+        let changed = with_entry(&session.entries, "one");
+        state.change(&mut writer, &mut session, changed);
+        assert_eq!(std::str::from_utf8(&writer), Ok("Saved.\n"));
+        assert!(std::fs::read_to_string(&path).unwrap().contains("\"one\""));
+        assert_eq!(builtin(&mut state, &mut session, "undo", ""), (Some(true), S("Undone.\nSaved.\n")));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "{}");
+    }
+
+    #[test]
+    fn test_shell_exit() {
+        let temp = mktemp::Temp::new_dir().unwrap();
+        let path = temp.join("pm.json");
+        let mut session = shell_session(&path);
+        let mut state = ShellState::new(&session, false);
+        assert_eq!(builtin(&mut state, &mut session, "exit", ""), (Some(false), S("")));
+        let changed = with_entry(&session.entries, "one");
+        state.change(&mut Vec::new(), &mut session, changed);
+        let prompt = "There are unsaved changes. Save them? (y/n) \n";
+        assert_eq!(builtin(&mut state, &mut session, "exit", "\n"), (Some(true), S(prompt)));
+        assert_eq!(builtin(&mut state, &mut session, "quit", "n\n"), (Some(false), S(prompt)));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "{}");
+        assert_eq!(builtin(&mut state, &mut session, "exit", "y\n"), (Some(false), format!("{}Saved.\n", prompt)));
+        assert!(std::fs::read_to_string(&path).unwrap().contains("\"one\""));
+    }
+
+    #[test]
+    fn test_shell_commands() {
+        // Every command the shell completes is one it knows.
+        for command in SHELL_COMMANDS.iter().filter(|command| !["help", "save", "undo", "exit"].contains(command)) {
+            let error = ShellLine::clap().get_matches_from_safe([command, "--help"]).unwrap_err();
+            assert_eq!(error.kind, structopt::clap::ErrorKind::HelpDisplayed, "{}", command);
+        }
+        assert!(!SHELL_COMMANDS.contains(&"agent"));
+    }
+
+    #[test]
+    fn test_shell_refusal() {
+        let refused = |line: &[&str]| refusal(&ShellLine::from_iter_safe(line).unwrap().command).is_some();
+        assert!(refused(&["agent"]));
+        assert!(refused(&["tui"]));
+        assert!(!refused(&["list"]));
+    }
+
+    #[test]
+    fn test_filter_flags() {
+        let filter = |line: &[&str]| match ShellLine::from_iter_safe(line).unwrap().command {
+            Command::List { in_folder: None, filter } | Command::Export { filter, .. } | Command::Env { filter, .. } => filter.filter(),
+            command => panic!("{:?}", command),
+        };
+        assert_eq!(filter(&["list"]), pm::Filter::default());
+        assert_eq!(filter(&["list", "--folder", "work", "--tag", "a", "--tag", "b"]), pm::Filter{folder: S("work"), all_tags: vec![S("a"), S("b")], ..pm::Filter::default()});
+        match ShellLine::from_iter_safe(&["list", "work", "--tag", "a"]).unwrap().command {
+            Command::List { in_folder, filter } => {
+                assert_eq!(in_folder, Some(S("work")));
+                assert_eq!(filter.filter(), pm::Filter{all_tags: vec![S("a")], ..pm::Filter::default()});
+            },
+            command => panic!("{:?}", command),
+        }
+        assert_eq!(filter(&["export", "--format", "csv", "--folder", "work", "--search", "aws"]), pm::Filter{folder: S("work"), search: S("aws"), ..pm::Filter::default()});
+        assert_eq!(filter(&["env", "--any-tag", "a", "--search", "aws"]), pm::Filter{any_tags: vec![S("a")], search: S("aws"), ..pm::Filter::default()});
+    }
+
+    #[test]
+    fn test_edit_in_editor() {
+        let github = pm::Entries::new().update(S("github"), pm::Entry{
+            name: S("github"),
+            username: S("octocat"),
+            password: S("hunter2"),
+            id: S("1a2b3c4d"),
+            ..pm::Entry::default()
+        });
+        let temp = mktemp::Temp::new_dir().unwrap();
+        let dir = temp.join("tmpfs");
+        std::fs::create_dir(&dir).unwrap();
+
+        let mut writer = Vec::new();
+        let editor = format!("f() {{ stat -c %a \"$1\" > {}/mode; sed -i 's/octocat/me/' \"$1\"; }}; f", temp.display());
+        let edited = edit_in_editor(&mut &(b"y\n")[..], &mut writer, github.clone(), "github", &editor, &dir);
+        assert_eq!(edited.get("github").unwrap().username, "me");
+        assert_eq!(edited.get("github").unwrap().id, "1a2b3c4d");
+        assert_eq!(std::str::from_utf8(&writer), Ok("- username = \"octocat\"\n+ username = \"me\"\nSave these changes? (y/n) \n"));
+        assert_eq!(std::fs::read_to_string(temp.join("mode")).unwrap(), "600\n");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+        // The password isn't shown, only that it changed.
+        let mut writer = Vec::new();
+        let edited = edit_in_editor(&mut &(b"y\n")[..], &mut writer, github.clone(), "github", "sed -i 's/hunter2/hunter3/'", &dir);
+        assert_eq!(edited.get("github").unwrap().password, "hunter3");
+        assert_eq!(std::str::from_utf8(&writer), Ok("password changed\nSave these changes? (y/n) \n"));
+
+        let mut writer = Vec::new();
+        assert_eq!(edit_in_editor(&mut &(b"n\n")[..], &mut writer, github.clone(), "github", "sed -i 's/octocat/me/'", &dir), github);
+        assert!(std::str::from_utf8(&writer).unwrap().ends_with("Nothing changed.\n"));
+
+        let mut writer = Vec::new();
+        assert_eq!(edit_in_editor(&mut &(b"")[..], &mut writer, github.clone(), "github", "true", &dir), github);
+        assert_eq!(std::str::from_utf8(&writer), Ok("Nothing changed.\n"));
+
+        let mut writer = Vec::new();
+        assert_eq!(edit_in_editor(&mut &(b"")[..], &mut writer, github.clone(), "github", "false", &dir), github);
+        assert_eq!(std::str::from_utf8(&writer), Ok("false quit with an error, so nothing changed.\n"));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_edit_in_editor_again() {
+        let github = pm::Entries::new().update(S("github"), pm::Entry{
+            name: S("github"),
+            username: S("octocat"),
+            ..pm::Entry::default()
+        });
+        let temp = mktemp::Temp::new_dir().unwrap();
+        // It breaks the file the first time and fixes it the second.
+        let editor = format!("f() {{ if [ -e {0}/once ]; then printf 'name = \"github\"\\nnotes = \"\"\"\\none\\ntwo\"\"\"\\n' > \"$1\"; else touch {0}/once; echo oops > \"$1\"; fi; }}; f", temp.display());
+        let mut writer = Vec::new();
+        let edited = edit_in_editor(&mut &(b"y\ny\n")[..], &mut writer, github.clone(), "github", &editor, &temp);
+        let output = String::from_utf8(writer).unwrap();
+        assert!(output.starts_with("That isn't an entry: "), "{}", output);
+        assert!(output.contains("Open it again to fix it? (y/n) \n"));
+        let entry = edited.get("github").unwrap();
+        assert_eq!(entry.notes, "one\ntwo");
+        assert_eq!(entry.username, "");
+
+        let mut writer = Vec::new();
+        assert_eq!(edit_in_editor(&mut &(b"n\n")[..], &mut writer, github.clone(), "github", "echo oops >", &temp), github);
+        assert!(std::str::from_utf8(&writer).unwrap().ends_with("Open it again to fix it? (y/n) \nNothing changed.\n"));
+    }
+
+    #[test]
+    fn test_ssh_keygen() {
+        let mut writer = Vec::new();
+        let entries = ssh_keygen(&mut writer, pm::Entries::new(), "ssh/github", "me@example.com");
+        let entry = entries.get("ssh/github").unwrap();
+        let public = entry.field(pm::ssh::PUBLIC_KEY_FIELD).unwrap();
+        assert!(public.starts_with("ssh-ed25519 ") && public.ends_with(" me@example.com"));
+        assert_eq!(std::str::from_utf8(&writer), Ok(format!("{}\n", public).as_str()));
+        assert_eq!(pm::ssh::private_key(entry).unwrap().public_key().to_openssh().unwrap(), public);
+        assert_eq!(entry.id.len(), 8);
+
+        let mut writer = Vec::new();
+        assert_eq!(ssh_keygen(&mut writer, entries.clone(), "ssh/github", "again"), entries);
+        assert_eq!(std::str::from_utf8(&writer), Ok("An entry with the name \"ssh/github\" already exists.\n"));
+        assert_eq!(ssh_keygen(&mut Vec::new(), entries.clone(), "ssh/", "x"), entries);
+    }
+
+    #[test]
+    fn test_credential() {
+        let entries = pm::Entries::new().update(S("email"), pm::Entry{
+            name: S("email"),
+            username: S("me"),
+            password: S("hunter2"),
+            notes: S(""),
+            id: S("1"),
+            tags: pm::Tags::new(),
+            fields: pm::Fields::new().update(S("url"), S("https://git.example.com")),
+        });
+
+        let mut writer = Vec::new();
+        let got = credential(&mut "protocol=https\nhost=git.example.com\n\n".as_bytes(), &mut writer, entries.clone(), "get");
+        assert_eq!(got, entries);
+        assert_eq!(std::str::from_utf8(&writer), Ok("username=me\npassword=hunter2\n"));
+
+        let mut writer = Vec::new();
+        credential(&mut "protocol=https\nhost=example.org\n".as_bytes(), &mut writer, entries.clone(), "get");
+        assert_eq!(writer, b"");
+
+        let mut writer = Vec::new();
+        let stored = credential(&mut "protocol=https\nhost=example.org\nusername=bot\npassword=token\n\n".as_bytes(), &mut writer, entries.clone(), "store");
+        assert_eq!(stored.get("git/example.org").map(|entry| entry.password.as_str()), Some("token"));
+        assert_eq!(writer, b"");
+
+        let erased = credential(&mut "protocol=https\nhost=example.org\nusername=bot\npassword=token\n".as_bytes(), &mut writer, stored.clone(), "erase");
+        assert_eq!(erased, entries);
+        assert_eq!(credential(&mut "protocol=https\nhost=example.org\n".as_bytes(), &mut writer, stored.clone(), "capabilities"), stored);
+    }
+
+    #[test]
+    fn test_tag_add_and_remove() {
+        let mut writer = Vec::new();
+        let entries = pm::Entries::new().update(S("one"), pm::Entry{
+            name: S("one"),
+            username: S("one username"),
+            password: S("one password"),
+            notes: S("one notes"),
+            id: S("1"),
+            tags: pm::parse_tags("work"),
+            fields: pm::Fields::new(),
+        }).update(S("two"), pm::Entry{
+            name: S("two"),
+            username: S("two username"),
+            password: S("two password"),
+            notes: S("two notes"),
+            id: S("2"),
+            tags: pm::Tags::new(),
+            fields: pm::Fields::new(),
+        });
+
+        let added = tag_add(&mut writer, entries.clone(), "work", &[S("one"), S("two")]);
+        assert_eq!(added.get("one").unwrap().tags, pm::parse_tags("work"));
+        assert_eq!(added.get("two").unwrap().tags, pm::parse_tags("work"));
+
+        let removed = tag_remove(&mut writer, added, "work", &[S("one")]);
+        assert_eq!(removed.get("one").unwrap().tags, pm::Tags::new());
+        assert_eq!(removed.get("two").unwrap().tags, pm::parse_tags("work"));
+    }
+
+    #[test]
+    fn test_mv() {
+        let mut writer = Vec::new();
+        let entries = pm::Entries::new().update(S("aws"), pm::Entry{
+            name: S("aws"),
+            username: S("aws username"),
+            password: S("aws password"),
+            notes: S("aws notes"),
+            id: S("aws"),
+            tags: pm::parse_tags("cloud"),
+            fields: pm::Fields::new(),
+        }).update(S("home/aws"), pm::Entry{
+            name: S("home/aws"),
+            username: S("home username"),
+            password: S("home password"),
+            notes: S("home notes"),
+            id: S("home"),
+            tags: pm::Tags::new(),
+            fields: pm::Fields::new(),
+        });
+
+        // Everything but the name is kept.
+        let renamed = mv(&mut writer, entries.clone(), "aws", "amazon", false);
+        assert_eq!(renamed.get("aws"), None);
+        assert_eq!(renamed.get("amazon"), Some(&pm::Entry{
+            name: S("amazon"),
+            ..entries.get("aws").unwrap().clone()
+        }));
+
+        // Into a folder, keeping the name.
+        let moved = mv(&mut writer, entries.clone(), "aws", "work/", false);
+        assert_eq!(moved.get("aws"), None);
+        assert_eq!(moved.get("work/aws"), Some(&pm::Entry{
+            name: S("work/aws"),
+            ..entries.get("aws").unwrap().clone()
+        }));
+
+        // Back to the top.
+        let moved_back = mv(&mut writer, moved, "work/aws", "/", false);
+        assert_eq!(moved_back, entries);
+
+        // Existing entries aren't replaced without force.
+        assert_eq!(mv(&mut writer, entries.clone(), "aws", "home/", false), entries);
+        assert_eq!(mv(&mut writer, entries.clone(), "aws", "home/aws", false), entries);
+        assert_eq!(mv(&mut writer, entries.clone(), "aws", "aws", true), entries);
+        assert_eq!(mv(&mut writer, entries.clone(), "aws", "bad//name", true), entries);
+        assert_eq!(mv(&mut writer, entries.clone(), "nope", "anything", true), entries);
+
+        let forced = mv(&mut writer, entries.clone(), "aws", "home/", true);
+        assert_eq!(forced, pm::Entries::new().update(S("home/aws"), pm::Entry{
+            name: S("home/aws"),
+            ..entries.get("aws").unwrap().clone()
+        }));
+    }
+
+    #[test]
+    fn test_import() {
+        let mut writer: Vec<u8> = Vec::new();
+        let entries = pm::Entries::new().update(S("taken"), pm::Entry{
+            name: S("taken"),
+            username: S("taken username"),
+            password: S("taken password"),
+            notes: S("taken notes"),
+            id: S("taken"),
+            tags: pm::Tags::new(),
+            fields: pm::Fields::new(),
+        });
+        let imported: pm::import::Imported = vec![S("new"), S("taken"), S("taken")].into_iter().map(|name| pm::Entry{
+            name,
+            username: S("imported username"),
+            password: S("imported password"),
+            ..pm::Entry::default()
+        }).collect::<Vec<_>>().into();
+        let names = |entries: &pm::Entries| entries.keys().cloned().collect::<Vec<_>>();
+
+        let mut reader = &(b"")[..];
+        let skipped = import(&mut reader, &mut writer, entries.clone(), imported.clone(), pm::import::Collision::Skip, false);
+        assert_eq!(names(&skipped), vec![S("new"), S("taken")]);
+        assert_eq!(skipped.get("taken"), entries.get("taken"));
+        assert_eq!(skipped.get("new").unwrap().id.len(), 8);
+
+        let suffixed = import(&mut reader, &mut writer, entries.clone(), imported.clone(), pm::import::Collision::Suffix, false);
+        assert_eq!(names(&suffixed), vec![S("new"), S("taken"), S("taken (2)"), S("taken (3)")]);
+        assert_eq!(suffixed.get("taken (2)").unwrap().name, "taken (2)");
+
+        // Overwrite the first, ask again after a bad answer, and rename the
+        // second.
+        let mut reader = &(b"o
+what
+r
+")[..];
+        let prompted = import(&mut reader, &mut writer, entries.clone(), imported.clone(), pm::import::Collision::Prompt, false);
+        assert_eq!(names(&prompted), vec![S("new"), S("taken"), S("taken (2)")]);
+        assert_eq!(prompted.get("taken").unwrap().password, "imported password");
+        assert_eq!(prompted.get("taken").unwrap().id, "taken");
+
+        // Running out of input skips the rest.
+        let mut reader = &(b"")[..];
+        let out_of_input = import(&mut reader, &mut writer, entries.clone(), imported.clone(), pm::import::Collision::Prompt, false);
+        assert_eq!(names(&out_of_input), vec![S("new"), S("taken")]);
+
+        // A dry run doesn't ask or change anything, but says what it would do.
+        let mut reader = &(b"")[..];
+        let mut writer: Vec<u8> = Vec::new();
+        let dry = import(&mut reader, &mut writer, entries.clone(), imported.clone(), pm::import::Collision::Suffix, true);
+        assert_eq!(dry, entries);
+        assert_eq!(std::str::from_utf8(&writer), Ok(concat!(
+            "Would add \"new\".\n",
+            "Would add \"taken\" as \"taken (2)\".\n",
+            "Would add \"taken\" as \"taken (3)\".\n",
+            "Would add 3 and skip 0. Nothing was saved.\n",
+        )));
+
+        // Rows the importer merged or left out are counted too.
+        let mut writer: Vec<u8> = Vec::new();
+        let merged = pm::import::Imported{merged: 2, skipped: vec![S("row 5, which doesn't have a password")], ..imported.clone()};
+        import(&mut reader, &mut writer, entries.clone(), merged, pm::import::Collision::Skip, false);
+        assert_eq!(std::str::from_utf8(&writer), Ok(concat!(
+            "Skipping row 5, which doesn't have a password.\n",
+            "Adding \"new\".\n",
+            "Skipping \"taken\".\n",
+            "Skipping \"taken\".\n",
+            "Added 1, merged 2, and skipped 3.\n",
+        )));
+    }
+
+    #[test]
+    fn test_confirm_secrets() {
+        let mut writer: Vec<u8> = Vec::new();
+        let mut reader = &(b"")[..];
+        assert_eq!(confirm_secrets(&mut reader, &mut writer, &[S("name"), S("url")]), Ok(()));
+        assert!(writer.is_empty());
+
+        // Running out of input is a no.
+        assert!(confirm_secrets(&mut reader, &mut writer, &[S("name"), S("password"), S("totp")]).is_err());
+        assert_eq!(std::str::from_utf8(&writer), Ok(concat!(
+            "The export will have these in it, unencrypted: password, totp. Use --no-secrets to leave them out.\n",
+            "Export them anyway? (y/N) \n",
+        )));
+
+        let mut reader = &(b"y\n")[..];
+        assert_eq!(confirm_secrets(&mut reader, &mut writer, &[S("password")]), Ok(()));
+    }
+
+    #[test]
+    fn test_share() {
+        let entries = pm::Entries::new().update(S("a"), pm::Entry{
+            name: S("a"),
+            username: S("a username"),
+            password: S("a password"),
+            notes: S("a notes"),
+            id: S("1a2b3c4d"),
+            tags: pm::Tags::new(),
+            fields: pm::Fields::new(),
+        }).update(S("b"), pm::Entry{
+            name: S("b"),
+            username: S("b username"),
+            password: S("b password"),
+            notes: S("b notes"),
+            id: S("5e6f7a8b"),
+            tags: pm::Tags::new(),
+            fields: pm::Fields::new(),
+        });
+        let (key, public) = pm::share::generate();
+        let identities = pm::share::parse_identities(&key).unwrap();
+
+        // Picking the same entry twice only shares it once.
+        let (bundle, count) = share(entries.clone(), &[S("a"), S("id:1a2b3c4d")], std::slice::from_ref(&public)).unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(pm::share::open(&bundle, &identities), Ok(vec![entries.get("a").unwrap().clone()]));
+
+        // Public keys can come from a file.
+        let recipients = mktemp::Temp::new_file().unwrap();
+        std::fs::write(&recipients, format!("# me\n{}\n", public)).unwrap();
+        let (bundle, count) = share(entries.clone(), &[S("a"), S("b")], &[recipients.to_str().unwrap().to_owned()]).unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(pm::share::open(&bundle, &identities).unwrap().len(), 2);
+
+        assert!(share(entries.clone(), &[S("c")], &[public]).is_err());
+        assert!(share(entries.clone(), &[S("a")], &[S("/nowhere")]).is_err());
+    }
+
+    #[test]
+    fn test_recipients() {
+        let path = std::path::PathBuf::from("/nowhere");
+        let mut writer: Vec<u8> = Vec::new();
+        recipients(&mut writer, &pm::vault::Vault::plain(&path));
+        assert_eq!(std::str::from_utf8(&writer), Ok("The file isn't encrypted. Add a recipient to encrypt it.\n"));
+
+        let vault = pm::vault::Vault{path, recipients: vec![
+            pm::vault::Recipient{name: S("alice"), public_key: S("age1alice"), passphrase_key: S("")},
+            pm::vault::Recipient{name: S("bob"), public_key: S("age1bob"), passphrase_key: S("-----BEGIN AGE ENCRYPTED FILE-----")},
+        ]};
+        let mut writer: Vec<u8> = Vec::new();
+        recipients(&mut writer, &vault);
+        assert_eq!(std::str::from_utf8(&writer), Ok("alice (key): age1alice\nbob (passphrase): age1bob\n"));
     }
 }
@@ -0,0 +1,332 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use clipboard::{ClipboardContext, ClipboardProvider};
+
+// Somewhere `clip` can put a password. The `clipboard` crate only knows about
+// X11, which doesn't help on Wayland or over SSH, so there's one of these for
+// each way of getting at a clipboard.
+pub trait Clipboard {
+    fn set(&mut self, contents: &str) -> Result<(), String>;
+    // Not every clipboard can be read. OSC 52 is write-only, for example.
+    fn get(&mut self) -> Result<String, String>;
+    fn clear(&mut self) -> Result<(), String> {
+        self.set("")
+    }
+    // Sets the contents and, if this clipboard can tell, waits until they've
+    // been pasted once. Returns whether it waited.
+    fn set_until_pasted(&mut self, contents: &str) -> Result<bool, String> {
+        self.set(contents).map(|_| false)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    // wl-copy and wl-paste.
+    Wayland,
+    // The X11 CLIPBOARD selection through xclip or xsel.
+    Xclip,
+    Xsel,
+    // The X11 CLIPBOARD selection through the `clipboard` crate. It's what pm
+    // always used and it doesn't need anything installed.
+    X11,
+    // The X11 PRIMARY selection, the one middle-click pastes, through xclip or
+    // xsel.
+    Primary,
+    // A tmux paste buffer.
+    Tmux,
+    // An escape sequence that asks the terminal to set its clipboard. It works
+    // over SSH as long as the terminal supports it.
+    Osc52,
+}
+
+pub const BACKEND_NAMES: &[&str] = &["wayland", "xclip", "xsel", "x11", "primary", "tmux", "osc52"];
+
+impl std::str::FromStr for Backend {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Backend, String> {
+        match name {
+            "wayland" => Ok(Backend::Wayland),
+            "xclip" => Ok(Backend::Xclip),
+            "xsel" => Ok(Backend::Xsel),
+            "x11" => Ok(Backend::X11),
+            "primary" => Ok(Backend::Primary),
+            "tmux" => Ok(Backend::Tmux),
+            "osc52" => Ok(Backend::Osc52),
+            _ => Err(format!("\"{}\" isn't a clipboard. Try one of: {}.", name, BACKEND_NAMES.join(", "))),
+        }
+    }
+}
+
+impl std::fmt::Display for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            Backend::Wayland => "wayland",
+            Backend::Xclip => "xclip",
+            Backend::Xsel => "xsel",
+            Backend::X11 => "x11",
+            Backend::Primary => "primary",
+            Backend::Tmux => "tmux",
+            Backend::Osc52 => "osc52",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Backend {
+    // Picks a clipboard based on the environment. A graphical session wins
+    // because that's where the browser is. Over SSH, the terminal on the other
+    // end is more useful than tmux's buffers on this one.
+    pub fn detect() -> Backend {
+        let set = |name: &str| std::env::var_os(name).is_some_and(|value| !value.is_empty());
+        if set("WAYLAND_DISPLAY") && installed("wl-copy") {
+            Backend::Wayland
+        } else if set("DISPLAY") {
+            if installed("xclip") {
+                Backend::Xclip
+            } else if installed("xsel") {
+                Backend::Xsel
+            } else {
+                Backend::X11
+            }
+        } else if set("SSH_CONNECTION") {
+            Backend::Osc52
+        } else if set("TMUX") {
+            Backend::Tmux
+        } else {
+            Backend::Osc52
+        }
+    }
+
+    pub fn open(self) -> Result<Box<dyn Clipboard>, String> {
+        Ok(match self {
+            Backend::Wayland => Box::new(Program{
+                copy: &["wl-copy"],
+                paste: &["wl-paste", "--no-newline"],
+                clear: Some(&["wl-copy", "--clear"]),
+                paste_once: Some(&["wl-copy", "--foreground", "--paste-once"]),
+            }),
+            Backend::Xclip => Box::new(Program{
+                copy: &["xclip", "-selection", "clipboard", "-in"],
+                paste: &["xclip", "-selection", "clipboard", "-out"],
+                clear: None,
+                paste_once: Some(&["xclip", "-quiet", "-loops", "1", "-selection", "clipboard", "-in"]),
+            }),
+            Backend::Xsel => Box::new(Program{
+                copy: &["xsel", "--clipboard", "--input"],
+                paste: &["xsel", "--clipboard", "--output"],
+                clear: Some(&["xsel", "--clipboard", "--delete"]),
+                paste_once: None,
+            }),
+            Backend::Primary => if installed("xclip") {
+                Box::new(Program{
+                    copy: &["xclip", "-selection", "primary", "-in"],
+                    paste: &["xclip", "-selection", "primary", "-out"],
+                    clear: None,
+                    paste_once: Some(&["xclip", "-quiet", "-loops", "1", "-selection", "primary", "-in"]),
+                })
+            } else {
+                Box::new(Program{
+                    copy: &["xsel", "--primary", "--input"],
+                    paste: &["xsel", "--primary", "--output"],
+                    clear: Some(&["xsel", "--primary", "--delete"]),
+                    paste_once: None,
+                })
+            },
+            Backend::Tmux => Box::new(Program{
+                copy: &["tmux", "load-buffer", "-"],
+                paste: &["tmux", "save-buffer", "-"],
+                clear: Some(&["tmux", "delete-buffer"]),
+                paste_once: None,
+            }),
+            Backend::X11 => Box::new(X11(ClipboardProvider::new()
+                .map_err(|e| format!("Failed getting access to the clipboard: {}", e))?)),
+            Backend::Osc52 => Box::new(Osc52),
+        })
+    }
+
+    // Whether the contents go away when pm exits. The programs all keep
+    // serving the contents after they're done, but the `clipboard` crate only
+    // does it for as long as we're running.
+    pub fn holds_contents(self) -> bool {
+        self == Backend::X11
+    }
+}
+
+// Checks if a program is somewhere in the PATH.
+fn installed(program: &str) -> bool {
+    std::env::var_os("PATH").is_some_and(|path| {
+        std::env::split_paths(&path).any(|dir| dir.join(program).is_file())
+    })
+}
+
+struct X11(ClipboardContext);
+
+impl Clipboard for X11 {
+    fn set(&mut self, contents: &str) -> Result<(), String> {
+        self.0.set_contents(contents.to_owned()).map_err(|e| format!("Failed setting clipboard contents: {}", e))
+    }
+
+    fn get(&mut self) -> Result<String, String> {
+        self.0.get_contents().map_err(|e| format!("Failed getting clipboard contents: {}", e))
+    }
+}
+
+// A clipboard that's driven by running programs like wl-copy and wl-paste.
+// `paste_once`, if there is one, sets the contents and doesn't exit until
+// they've been pasted.
+struct Program {
+    copy: &'static [&'static str],
+    paste: &'static [&'static str],
+    clear: Option<&'static [&'static str]>,
+    paste_once: Option<&'static [&'static str]>,
+}
+
+impl Program {
+    // Runs a command, giving it `input` if there is any and returning its
+    // output if there isn't. The copying programs fork to keep serving the
+    // contents, and that fork would hold a piped stdout open for as long as
+    // it's alive, so it's only piped when there's output to read.
+    fn run(command: &[&str], input: Option<&str>) -> Result<String, String> {
+        let mut child = Command::new(command[0])
+            .args(&command[1..])
+            .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+            .stdout(if input.is_some() { Stdio::null() } else { Stdio::piped() })
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("Failed running {}: {}", command[0], e))?;
+        if let Some(input) = input {
+            // Taking stdin closes it once it's written, so the program knows
+            // that's all there is.
+            child.stdin.take().unwrap().write_all(input.as_bytes())
+                .map_err(|e| format!("Failed writing to {}: {}", command[0], e))?;
+        }
+        let output = child.wait_with_output().map_err(|e| format!("Failed running {}: {}", command[0], e))?;
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+        } else {
+            Err(format!("{} failed with {}.", command[0], output.status))
+        }
+    }
+}
+
+impl Clipboard for Program {
+    fn set(&mut self, contents: &str) -> Result<(), String> {
+        Program::run(self.copy, Some(contents)).map(|_| ())
+    }
+
+    fn get(&mut self) -> Result<String, String> {
+        Program::run(self.paste, None)
+    }
+
+    fn clear(&mut self) -> Result<(), String> {
+        match self.clear {
+            None => self.set(""),
+            Some(command) => Program::run(command, None).map(|_| ()),
+        }
+    }
+
+    fn set_until_pasted(&mut self, contents: &str) -> Result<bool, String> {
+        match self.paste_once {
+            None => self.set(contents).map(|_| false),
+            Some(command) => Program::run(command, Some(contents)).map(|_| true),
+        }
+    }
+}
+
+// Sets the clipboard of whatever terminal we're in with the OSC 52 escape
+// sequence. It's written to the terminal directly so that it works even when
+// stdout is redirected.
+struct Osc52;
+
+impl Clipboard for Osc52 {
+    fn set(&mut self, contents: &str) -> Result<(), String> {
+        let mut sequence = format!("\x1b]52;c;{}\x07", base64(contents.as_bytes()));
+        // tmux swallows escape sequences it doesn't know unless they're
+        // wrapped up like this.
+        if std::env::var_os("TMUX").is_some() {
+            sequence = format!("\x1bPtmux;{}\x1b\\", sequence.replace('\x1b', "\x1b\x1b"));
+        }
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open("/dev/tty")
+            .and_then(|mut tty| tty.write_all(sequence.as_bytes()))
+            .map_err(|e| format!("Failed writing to the terminal: {}", e))
+    }
+
+    fn get(&mut self) -> Result<String, String> {
+        Err(String::from("The terminal's clipboard can't be read with OSC 52."))
+    }
+}
+
+// A clipboard that only lives in memory, for tests.
+#[cfg(test)]
+#[derive(Default)]
+pub struct Fake {
+    pub contents: String,
+}
+
+#[cfg(test)]
+impl Clipboard for Fake {
+    fn set(&mut self, contents: &str) -> Result<(), String> {
+        self.contents = contents.to_owned();
+        Ok(())
+    }
+
+    fn get(&mut self) -> Result<String, String> {
+        Ok(self.contents.clone())
+    }
+}
+
+// Standard base64 with padding. OSC 52 is the only thing that needs it, so it
+// isn't worth a crate.
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as usize) << 16 | (b[1] as usize) << 8 | b[2] as usize;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[n >> (18 - 6 * i) & 0x3f] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+        assert_eq!(base64(b"hunter2!"), "aHVudGVyMiE=");
+    }
+
+    #[test]
+    fn test_backend_names() {
+        for name in BACKEND_NAMES {
+            let backend: Backend = name.parse().unwrap();
+            assert_eq!(&backend.to_string(), name);
+        }
+        assert!("pasteboard".parse::<Backend>().is_err());
+    }
+
+    #[test]
+    fn test_fake() {
+        let mut board = Fake::default();
+        board.set("password").unwrap();
+        assert_eq!(board.get(), Ok(String::from("password")));
+        board.clear().unwrap();
+        assert_eq!(board.get(), Ok(String::new()));
+    }
+}
@@ -0,0 +1,279 @@
+use crate::{Entries, EntriesStuff, Entry};
+
+// What git asks a credential helper about. It comes as key=value lines, ended
+// by a blank line or the end of the input. See gitcredentials(7).
+//
+// The host has the port in it, if there is one. The path is only there when
+// credential.useHttpPath is set.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Credential {
+    pub protocol: String,
+    pub host: String,
+    pub path: String,
+    pub username: String,
+    pub password: String,
+}
+
+impl Credential {
+    pub fn parse(text: &str) -> Credential {
+        let mut credential = Credential::default();
+        for line in text.lines().take_while(|line| !line.is_empty()) {
+            let (key, value) = match line.find('=') {
+                Some(i) => (&line[..i], line[i + 1..].to_owned()),
+                None => continue,
+            };
+            match key {
+                "protocol" => credential.protocol = value,
+                "host" => credential.host = value,
+                "path" => credential.path = value,
+                "username" => credential.username = value,
+                "password" => credential.password = value,
+                "url" => {
+                    let from_url = Credential::from_url(&value);
+                    credential.protocol = from_url.protocol;
+                    credential.host = from_url.host;
+                    credential.path = from_url.path;
+                    if !from_url.username.is_empty() {
+                        credential.username = from_url.username;
+                    }
+                },
+                // Newer versions of git send things like capability[] that
+                // we have no use for.
+                _ => {},
+            }
+        }
+        credential
+    }
+
+    // Reads a URL like the ones kept in an entry's "url" field. Without a
+    // scheme, like "github.com", it's for any protocol.
+    pub fn from_url(url: &str) -> Credential {
+        let url = url.trim();
+        let (protocol, rest) = match url.find("://") {
+            Some(i) => (&url[..i], &url[i + 3..]),
+            None => ("", url),
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i + 1..]),
+            None => (rest, ""),
+        };
+        let (username, host) = match authority.rfind('@') {
+            Some(i) => (&authority[..i], &authority[i + 1..]),
+            None => ("", authority),
+        };
+        Credential{
+            protocol: protocol.to_lowercase(),
+            host: host.to_lowercase(),
+            path: path.split(['?', '#']).next().unwrap_or("").to_owned(),
+            username: username.to_owned(),
+            password: String::new(),
+        }
+    }
+
+    pub fn url(&self) -> String {
+        let path = if self.path.is_empty() { String::new() } else { format!("/{}", self.path) };
+        format!("{}://{}{}", self.protocol, self.host, path)
+    }
+
+    // What `get` answers with. Without a username, git asks for one.
+    pub fn format(&self) -> String {
+        if self.username.is_empty() {
+            format!("password={}\n", self.password)
+        } else {
+            format!("username={}\npassword={}\n", self.username, self.password)
+        }
+    }
+}
+
+// Paths are compared without the slashes around them or a ".git" at the end,
+// since git isn't consistent about either.
+fn same_path(a: &str, b: &str) -> bool {
+    let clean = |path: &str| path.trim_matches('/').trim_end_matches(".git").trim_end_matches('/').to_owned();
+    clean(a) == clean(b)
+}
+
+// How well an entry's URL matches what git asked for, if it does at all. One
+// that names the protocol or the path is a better match than one that
+// doesn't.
+fn score(url: &Credential, wanted: &Credential) -> Option<u8> {
+    if url.host.is_empty() || url.host != wanted.host.to_lowercase() {
+        return None;
+    }
+    if !url.protocol.is_empty() && url.protocol != wanted.protocol {
+        return None;
+    }
+    if !url.path.is_empty() && !same_path(&url.path, &wanted.path) {
+        return None;
+    }
+    Some(!url.path.is_empty() as u8 * 2 + !url.protocol.is_empty() as u8)
+}
+
+fn urls(entry: &Entry) -> impl Iterator<Item = Credential> + '_ {
+    entry.fields.iter()
+        .filter(|(name, _)| *name == "url" || name.starts_with("url "))
+        .map(|(_, url)| Credential::from_url(url))
+}
+
+// Finds the entry that best matches, by its URLs and, if git gave one, the
+// username. Ties go to the first by name.
+pub fn find(entries: &Entries, wanted: &Credential) -> Option<Entry> {
+    let mut best: Option<(u8, &Entry)> = None;
+    for entry in entries.values() {
+        if !wanted.username.is_empty() && entry.username != wanted.username {
+            continue;
+        }
+        if let Some(score) = urls(entry).filter_map(|url| score(&url, wanted)).max() {
+            if best.is_none_or(|(best_score, _)| score > best_score) {
+                best = Some((score, entry));
+            }
+        }
+    }
+    best.map(|(_, entry)| entry.clone())
+}
+
+// Remembers a username and password that worked. If there's already an entry
+// for them, its password is updated. Otherwise there's a new one, named after
+// the host and path, like "git/github.com/me/repo", and tagged "git".
+pub fn store(entries: Entries, credential: &Credential) -> Entries {
+    if credential.host.is_empty() || credential.username.is_empty() || credential.password.is_empty() {
+        return entries;
+    }
+    if let Some(existing) = find(&entries, credential) {
+        if existing.password == credential.password {
+            return entries;
+        }
+        return entries.update(existing.name.clone(), Entry{password: credential.password.clone(), ..existing});
+    }
+    let path = credential.path.trim_matches('/');
+    let name = if path.is_empty() {
+        format!("git/{}", credential.host)
+    } else {
+        format!("git/{}/{}", credential.host, path)
+    };
+    let name = if entries.contains_key(&name) {
+        crate::import::suffixed(|name| entries.contains_key(name), &name)
+    } else {
+        name
+    };
+    let entry = Entry{
+        name: name.clone(),
+        username: credential.username.clone(),
+        password: credential.password.clone(),
+        notes: String::new(),
+        id: entries.new_id(),
+        tags: crate::parse_tags("git"),
+        fields: crate::Fields::new().update(String::from("url"), credential.url()),
+    };
+    entries.update(name, entry)
+}
+
+// Forgets credentials that didn't work. Only entries that `store` made, the
+// ones tagged "git", are deleted; a password someone typed in themselves is
+// never thrown away because of a failed login. If git says which password
+// failed, it has to be that one.
+pub fn erase(entries: Entries, credential: &Credential) -> Entries {
+    entries.iter()
+        .filter(|(_, entry)| {
+            let matches = entry.tags.contains("git")
+                && (credential.username.is_empty() || entry.username == credential.username)
+                && (credential.password.is_empty() || entry.password == credential.password)
+                && urls(entry).any(|url| score(&url, credential).is_some());
+            !matches
+        })
+        .map(|(name, entry)| (name.clone(), entry.clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Fields;
+
+    static S: fn(&'static str) -> String = String::from;
+
+    fn entry(name: &str, username: &str, url: &str) -> Entry {
+        Entry{
+            name: name.to_owned(),
+            username: username.to_owned(),
+            password: format!("{} password", name),
+            notes: S(""),
+            id: S(""),
+            tags: crate::Tags::new(),
+            fields: Fields::new().update(S("url"), url.to_owned()),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let credential = Credential::parse("protocol=https\nhost=example.com:8443\npath=me/repo.git\nusername=me\ncapability[]=authtype\n\nhost=ignored\n");
+        assert_eq!(credential, Credential{
+            protocol: S("https"),
+            host: S("example.com:8443"),
+            path: S("me/repo.git"),
+            username: S("me"),
+            password: S(""),
+        });
+        assert_eq!(Credential::parse("url=https://me@Example.com/repo\n"), Credential{
+            protocol: S("https"),
+            host: S("example.com"),
+            path: S("repo"),
+            username: S("me"),
+            password: S(""),
+        });
+        assert_eq!(Credential::from_url("github.com").host, "github.com");
+        assert_eq!(credential.url(), "https://example.com:8443/me/repo.git");
+        assert_eq!(Credential{username: S("me"), password: S("hunter2"), ..Credential::default()}.format(), "username=me\npassword=hunter2\n");
+        assert_eq!(Credential{password: S("hunter2"), ..Credential::default()}.format(), "password=hunter2\n");
+    }
+
+    #[test]
+    fn test_find() {
+        let entries = Entries::new()
+            .update(S("any"), entry("any", "me", "example.com"))
+            .update(S("https"), entry("https", "me", "https://example.com"))
+            .update(S("repo"), entry("repo", "me", "https://example.com/team/repo"))
+            .update(S("other"), entry("other", "someone", "https://example.com"))
+            .update(S("port"), entry("port", "me", "http://127.0.0.1:8080/"));
+        let find_name = |text: &str| find(&entries, &Credential::parse(text)).map(|entry| entry.name);
+
+        assert_eq!(find_name("protocol=https\nhost=example.com\n"), Some(S("https")));
+        assert_eq!(find_name("protocol=ssh\nhost=example.com\n"), Some(S("any")));
+        assert_eq!(find_name("protocol=https\nhost=example.com\npath=team/repo.git\n"), Some(S("repo")));
+        assert_eq!(find_name("protocol=https\nhost=example.com\npath=team/other.git\n"), Some(S("https")));
+        assert_eq!(find_name("protocol=https\nhost=example.com\nusername=someone\n"), Some(S("other")));
+        assert_eq!(find_name("protocol=http\nhost=127.0.0.1:8080\n"), Some(S("port")));
+        assert_eq!(find_name("protocol=http\nhost=127.0.0.1\n"), None);
+        assert_eq!(find_name("protocol=https\nhost=example.org\n"), None);
+    }
+
+    #[test]
+    fn test_store_and_erase() {
+        let mine = entry("mine", "me", "https://example.com");
+        let entries = Entries::new().update(S("mine"), mine.clone());
+
+        // An entry that matches gets the new password.
+        let credential = Credential::parse("protocol=https\nhost=example.com\nusername=me\npassword=new\n");
+        let stored = store(entries.clone(), &credential);
+        assert_eq!(stored.get("mine").unwrap().password, "new");
+        assert_eq!(stored.len(), 1);
+
+        // Otherwise there's a new one.
+        let credential = Credential::parse("protocol=https\nhost=example.org\npath=team/repo.git\nusername=bot\npassword=token\n");
+        let stored = store(entries.clone(), &credential);
+        let new = stored.get("git/example.org/team/repo.git").unwrap();
+        assert_eq!((new.username.as_str(), new.password.as_str()), ("bot", "token"));
+        assert_eq!(new.field("url"), Some("https://example.org/team/repo.git"));
+        assert_eq!(new.tags, crate::parse_tags("git"));
+        assert_eq!(new.id.len(), 8);
+        assert_eq!(store(stored.clone(), &credential), stored);
+
+        // Without a username or password there's nothing to store.
+        assert_eq!(store(entries.clone(), &Credential::parse("protocol=https\nhost=example.org\n")), entries);
+
+        // Only what was stored gets erased, and only with the same password.
+        let both = stored;
+        assert_eq!(erase(both.clone(), &Credential::parse("protocol=https\nhost=example.com\nusername=me\n")), both);
+        assert_eq!(erase(both.clone(), &Credential::parse("protocol=https\nhost=example.org\npath=team/repo.git\npassword=old\n")), both);
+        assert_eq!(erase(both.clone(), &Credential::parse("protocol=https\nhost=example.org\npath=team/repo.git\npassword=token\n")), entries);
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{Entries, Entry};
+
+// An entry as a TOML document, for editing all at once in an editor. Unlike
+// the prompts, the notes can have more than one line in them. The ID isn't
+// here because it can't change.
+#[derive(serde_derive::Serialize, serde_derive::Deserialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct Document {
+    name: String,
+    #[serde(default)]
+    username: String,
+    #[serde(default)]
+    password: String,
+    #[serde(default)]
+    notes: String,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    fields: BTreeMap<String, String>,
+}
+
+pub fn to_toml(entry: &Entry) -> String {
+    let document = Document{
+        name: entry.name.clone(),
+        username: entry.username.clone(),
+        password: entry.password.clone(),
+        notes: entry.notes.clone(),
+        tags: entry.tags.iter().cloned().collect(),
+        fields: entry.fields.iter().map(|(field, value)| (field.clone(), value.clone())).collect(),
+    };
+    let text = toml::to_string(&document).expect("Failed serializing an entry.");
+    format!("# ID: {} (it can't be changed)\n{}", entry.id, text)
+}
+
+// Reads an edited document back into the entry it came from. It's checked the
+// same way the prompts check things, and the new name can't be another
+// entry's.
+pub fn from_toml(text: &str, original: &Entry, entries: &Entries) -> Result<Entry, String> {
+    let document: Document = toml::from_str(text).map_err(|e| format!("That isn't an entry: {}", e.message()))?;
+    crate::check_name(&document.name)?;
+    if document.name != original.name && entries.contains_key(&document.name) {
+        return Err(format!("An entry with the name \"{}\" already exists.", document.name));
+    }
+    let tags: crate::Tags = document.tags.iter().cloned().collect();
+    tags.iter().try_for_each(|tag| crate::check_tag(tag))?;
+    if let Some(field) = document.fields.keys().find(|field| ["name", "username", "password", "notes", "id"].contains(&field.as_str())) {
+        return Err(format!("\"{}\" is already a field. Put it at the top instead of under [fields].", field));
+    }
+    Ok(Entry{
+        name: document.name,
+        username: document.username,
+        password: document.password,
+        notes: document.notes,
+        id: original.id.clone(),
+        tags,
+        fields: document.fields.into_iter().collect(),
+    })
+}
+
+// What changed between an entry and its edited version, to check before
+// saving. It's the diff of their documents, except that secrets are left out
+// of it and get a line like "password changed" instead, so that editing an
+// entry doesn't put its password on the screen.
+pub fn changes(before: &Entry, after: &Entry) -> Vec<String> {
+    let public = |entry: &Entry| Entry{
+        password: String::new(),
+        notes: String::new(),
+        fields: entry.fields.iter().filter(|(field, _)| !crate::export::is_secret(field)).map(|(field, value)| (field.clone(), value.clone())).collect(),
+        ..entry.clone()
+    };
+    let mut lines = diff(&to_toml(&public(before)), &to_toml(&public(after)));
+    if before.password != after.password {
+        lines.push(String::from("password changed"));
+    }
+    if before.notes != after.notes {
+        lines.push(String::from("notes changed"));
+    }
+    let fields: BTreeSet<&String> = before.fields.keys().chain(after.fields.keys()).filter(|field| crate::export::is_secret(field)).collect();
+    for field in fields {
+        match (before.fields.get(field), after.fields.get(field)) {
+            (None, Some(_)) => lines.push(format!("{} added", field)),
+            (Some(_), None) => lines.push(format!("{} removed", field)),
+            (was, is) if was != is => lines.push(format!("{} changed", field)),
+            _ => {},
+        }
+    }
+    lines
+}
+
+// A line diff between two documents, with "- " for lines that were taken out
+// and "+ " for lines that were put in. Lines that stay are left out, since
+// the point is to check the changes.
+pub fn diff(before: &str, after: &str) -> Vec<String> {
+    let before: Vec<&str> = before.lines().collect();
+    let after: Vec<&str> = after.lines().collect();
+    // The longest common subsequence from each pair of positions to the end.
+    let mut common = vec![vec![0; after.len() + 1]; before.len() + 1];
+    for i in (0..before.len()).rev() {
+        for j in (0..after.len()).rev() {
+            common[i][j] = if before[i] == after[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut lines = Vec::new();
+    while i < before.len() || j < after.len() {
+        if i < before.len() && j < after.len() && before[i] == after[j] {
+            i += 1;
+            j += 1;
+        } else if j == after.len() || i < before.len() && common[i + 1][j] >= common[i][j + 1] {
+            lines.push(format!("- {}", before[i]));
+            i += 1;
+        } else {
+            lines.push(format!("+ {}", after[j]));
+            j += 1;
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static S: fn(&'static str) -> String = String::from;
+
+    #[test]
+    fn test_round_trip() {
+        let entry = Entry{
+            name: S("github"),
+            username: S("octocat"),
+            password: S("hunter2"),
+            notes: S("Recovery codes:\n1234\n5678"),
+            id: S("1a2b3c4d"),
+            tags: crate::parse_tags("code work"),
+            fields: crate::Fields::new().update(S("url"), S("https://github.com")),
+        };
+        let text = to_toml(&entry);
+        assert!(text.starts_with("# ID: 1a2b3c4d (it can't be changed)\n"));
+        // The notes are still lines that can be edited.
+        assert!(text.contains("\nRecovery codes:\n1234\n5678"));
+        assert_eq!(from_toml(&text, &entry, &Entries::new()), Ok(entry.clone()));
+    }
+
+    #[test]
+    fn test_from_toml() {
+        let entry = Entry{
+            name: S("github"),
+            password: S("hunter2"),
+            id: S("1a2b3c4d"),
+            tags: crate::parse_tags("code work"),
+            fields: crate::Fields::new().update(S("url"), S("https://github.com")),
+            ..Entry::default()
+        };
+        let text = "name = \"work/github\"\nusername = \"me\"\nnotes = \"\"\"\none\ntwo\"\"\"\n[fields]\npin = \"1234\"\n";
+        let edited = from_toml(text, &entry, &Entries::new()).unwrap();
+        assert_eq!(edited.name, "work/github");
+        assert_eq!(edited.password, "");
+        assert_eq!(edited.notes, "one\ntwo");
+        assert_eq!(edited.id, "1a2b3c4d");
+        assert_eq!(edited.tags, crate::Tags::new());
+        assert_eq!(edited.fields, crate::Fields::new().update(S("pin"), S("1234")));
+
+        let taken = Entries::new().update(S("gitlab"), Entry::default());
+        assert_eq!(from_toml("name = \"gitlab\"", &entry, &taken), Err(S("An entry with the name \"gitlab\" already exists.")));
+        assert!(from_toml("name = \"github/\"", &entry, &Entries::new()).is_err());
+        assert!(from_toml("name = \"github\"\ntags = [\"two words\"]", &entry, &Entries::new()).is_err());
+        assert!(from_toml("name = \"github\"\n[fields]\npassword = \"x\"", &entry, &Entries::new()).is_err());
+        assert!(from_toml("name = \"github\"\nid = \"x\"", &entry, &Entries::new()).is_err());
+        assert!(from_toml("username = \"me\"", &entry, &Entries::new()).is_err());
+        assert!(from_toml("name = ", &entry, &Entries::new()).is_err());
+    }
+
+    #[test]
+    fn test_changes() {
+        let entry = Entry{
+            name: S("github"),
+            username: S("octocat"),
+            password: S("hunter2"),
+            notes: S("1234"),
+            fields: crate::Fields::new().update(S("url"), S("https://github.com")).update(S("pin"), S("0000")),
+            ..Entry::default()
+        };
+        assert_eq!(changes(&entry, &entry), Vec::<String>::new());
+        let edited = Entry{
+            username: S("me"),
+            password: S("hunter3"),
+            fields: crate::Fields::new().update(S("url"), S("https://github.com/login")).update(S("otp"), S("secret")),
+            ..entry.clone()
+        };
+        assert_eq!(changes(&entry, &edited), vec![
+            S("- username = \"octocat\""),
+            S("+ username = \"me\""),
+            S("- url = \"https://github.com\""),
+            S("+ url = \"https://github.com/login\""),
+            S("password changed"),
+            S("otp added"),
+            S("pin removed"),
+        ]);
+        assert_eq!(changes(&entry, &Entry{notes: S("5678"), ..entry.clone()}), vec![S("notes changed")]);
+    }
+
+    #[test]
+    fn test_diff() {
+        assert_eq!(diff("a\nb\nc\n", "a\nb\nc\n"), Vec::<String>::new());
+        assert_eq!(diff("a\nb\nc\n", "a\nB\nc\nd\n"), vec![S("- b"), S("+ B"), S("+ d")]);
+        assert_eq!(diff("a\nb\n", "b\n"), vec![S("- a")]);
+        assert_eq!(diff("", "a\n"), vec![S("+ a")]);
+    }
+}
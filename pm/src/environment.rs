@@ -0,0 +1,163 @@
+use crate::Entry;
+
+// The ways `pm env` can write entries' passwords out as environment variables.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    // KEY='value' lines, for a .env file.
+    Dotenv,
+    // export KEY='value' lines, for `eval` in sh, bash, or zsh.
+    Sh,
+    // set -gx KEY 'value' lines, for `source` in fish.
+    Fish,
+    // An object with a key per variable.
+    Json,
+}
+
+pub const FORMAT_NAMES: &[&str] = &["dotenv", "sh", "fish", "json"];
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Format, String> {
+        match name {
+            "dotenv" => Ok(Format::Dotenv),
+            "sh" => Ok(Format::Sh),
+            "fish" => Ok(Format::Fish),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("\"{}\" isn't a format I can write variables in. Try one of: {}.", name, FORMAT_NAMES.join(", "))),
+        }
+    }
+}
+
+// The custom field that names an entry's variable.
+pub const NAME_FIELD: &str = "env";
+
+// Works out the variable an entry goes in. It's the entry's "env" field if it
+// has one. Otherwise it's made from the name, so "myservice/db-password" is
+// MYSERVICE_DB_PASSWORD.
+pub fn variable_name(entry: &Entry) -> Result<String, String> {
+    if let Some(name) = entry.fields.get(NAME_FIELD) {
+        let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        return if valid {
+            Ok(name.clone())
+        } else {
+            Err(format!("\"{}\" has \"{}\" in its env field, which isn't a variable name.", entry.name, name))
+        };
+    }
+    let name: String = entry.name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect();
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        Ok(format!("_{}", name))
+    } else {
+        Ok(name)
+    }
+}
+
+// Pairs each entry's variable with its password. Two entries that would set
+// the same variable are an error, since one would quietly win.
+pub fn variables(entries: &[Entry]) -> Result<Vec<(String, String)>, String> {
+    let mut variables: Vec<(String, String)> = Vec::new();
+    let mut owners: Vec<&str> = Vec::new();
+    for entry in entries {
+        let name = variable_name(entry)?;
+        if let Some(i) = variables.iter().position(|(taken, _)| *taken == name) {
+            return Err(format!("\"{}\" and \"{}\" would both be {}. Give one of them an env field.", owners[i], entry.name, name));
+        }
+        variables.push((name, entry.password.clone()));
+        owners.push(&entry.name);
+    }
+    Ok(variables)
+}
+
+// Writes the variables out. Values are quoted so that each format reads them
+// back exactly, whatever is in them.
+pub fn format(format: Format, variables: &[(String, String)]) -> String {
+    match format {
+        Format::Dotenv => variables.iter()
+            .map(|(name, value)| format!("{}={}\n", name, dotenv_quote(value)))
+            .collect(),
+        Format::Sh => variables.iter()
+            .map(|(name, value)| format!("export {}={}\n", name, sh_quote(value)))
+            .collect(),
+        Format::Fish => variables.iter()
+            .map(|(name, value)| format!("set -gx {} {}\n", name, fish_quote(value)))
+            .collect(),
+        Format::Json => {
+            let object: serde_json::Map<String, serde_json::Value> = variables.iter()
+                .map(|(name, value)| (name.clone(), serde_json::Value::from(value.as_str())))
+                .collect();
+            format!("{}\n", serde_json::to_string_pretty(&object).expect("Failed serializing variables."))
+        },
+    }
+}
+
+// Single quotes keep everything as it is in sh, even newlines. A single quote
+// itself has to end the quoting, be escaped, and start it again.
+fn sh_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+// In fish, single quotes only need backslashes and single quotes escaped.
+fn fish_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\\', r"\\").replace('\'', r"\'"))
+}
+
+// dotenv readers agree that single quotes are literal, but they can't have a
+// single quote or a newline in them. Those get double quotes, where the
+// readers agree on \n, \", and \\. A $ in double quotes might be expanded by
+// some of them, so single quotes are used whenever they can be.
+fn dotenv_quote(value: &str) -> String {
+    if !value.contains(['\'', '\n', '\r']) {
+        format!("'{}'", value)
+    } else {
+        let escaped = value.replace('\\', r"\\").replace('"', "\\\"").replace('\n', r"\n").replace('\r', r"\r");
+        format!("\"{}\"", escaped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static S: fn(&'static str) -> String = String::from;
+
+    fn entry(name: &str, password: &str) -> Entry {
+        Entry{name: name.to_owned(), password: password.to_owned(), ..Entry::default()}
+    }
+
+    #[test]
+    fn test_variable_name() {
+        assert_eq!(variable_name(&entry("myservice/db-password", "")), Ok(S("MYSERVICE_DB_PASSWORD")));
+        assert_eq!(variable_name(&entry("2fa key", "")), Ok(S("_2FA_KEY")));
+        let named = Entry{fields: crate::Fields::new().update(S("env"), S("DATABASE_URL")), ..entry("db", "")};
+        assert_eq!(variable_name(&named), Ok(S("DATABASE_URL")));
+        let bad = Entry{fields: crate::Fields::new().update(S("env"), S("X; rm -rf ~")), ..entry("db", "")};
+        assert!(variable_name(&bad).is_err());
+    }
+
+    #[test]
+    fn test_variables() {
+        assert_eq!(variables(&[entry("a", "1"), entry("b", "2")]), Ok(vec![(S("A"), S("1")), (S("B"), S("2"))]));
+        assert_eq!(variables(&[entry("a-b", "1"), entry("a_b", "2")]), Err(S("\"a-b\" and \"a_b\" would both be A_B. Give one of them an env field.")));
+    }
+
+    #[test]
+    fn test_format() {
+        let variables = vec![(S("PLAIN"), S("hunter2")), (S("TRICKY"), S("it's $HOME \\ \"x\"\nline"))];
+        assert_eq!(format(Format::Dotenv, &variables), "PLAIN='hunter2'\nTRICKY=\"it's $HOME \\\\ \\\"x\\\"\\nline\"\n");
+        assert_eq!(format(Format::Sh, &variables), "export PLAIN='hunter2'\nexport TRICKY='it'\\''s $HOME \\ \"x\"\nline'\n");
+        assert_eq!(format(Format::Fish, &variables), "set -gx PLAIN 'hunter2'\nset -gx TRICKY 'it\\'s $HOME \\\\ \"x\"\nline'\n");
+        assert_eq!(format(Format::Json, &variables), "{\n  \"PLAIN\": \"hunter2\",\n  \"TRICKY\": \"it's $HOME \\\\ \\\"x\\\"\\nline\"\n}\n");
+    }
+
+    #[test]
+    fn test_sh_round_trip() {
+        // The shell should read back exactly what went in.
+        let value = "it's $HOME `x` \\ \"y\"\nline";
+        let script = format!("{}printf %s \"$TRICKY\"", format(Format::Sh, &[(S("TRICKY"), S(value))]));
+        let output = std::process::Command::new("sh").arg("-c").arg(script).output().unwrap();
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), value);
+    }
+}
@@ -0,0 +1,198 @@
+use std::os::unix::process::ExitStatusExt;
+use std::process::Command;
+use std::sync::atomic::{AtomicI32, Ordering};
+
+use pm::EntriesStuff;
+
+// An environment variable for `pm exec`, like "TOKEN=github:password". The
+// field comes after the last colon, so entries can be picked by ID too, like
+// "TOKEN=id:1a2b3c4d:password".
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variable {
+    pub name: String,
+    pub entry: String,
+    pub field: String,
+}
+
+impl std::str::FromStr for Variable {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Variable, String> {
+        let (name, value) = text.split_once('=')
+            .ok_or_else(|| format!("\"{}\" should look like NAME=entry:field.", text))?;
+        let (entry, field) = value.rsplit_once(':')
+            .ok_or_else(|| format!("\"{}\" should look like NAME=entry:field.", text))?;
+        if name.is_empty() || entry.is_empty() || field.is_empty() {
+            return Err(format!("\"{}\" should look like NAME=entry:field.", text));
+        }
+        Ok(Variable{name: name.to_owned(), entry: entry.to_owned(), field: field.to_owned()})
+    }
+}
+
+// Looks up every variable's value. It's all or nothing, so that the command
+// never runs with some of them missing.
+pub fn environment(entries: &pm::Entries, variables: &[Variable]) -> Result<Vec<(String, String)>, String> {
+    variables.iter()
+        .map(|variable| {
+            let entry = entries.getish(&variable.entry)?;
+            match entry.field(&variable.field) {
+                None => Err(format!("\"{}\" doesn't have a field called \"{}\".", entry.name, variable.field)),
+                Some(value) => Ok((variable.name.clone(), value.to_owned())),
+            }
+        })
+        .collect()
+}
+
+// The child, for the signal handler. It's 0 when there isn't one.
+static CHILD: AtomicI32 = AtomicI32::new(0);
+
+// Passes signals along to the child. The terminal already sends Ctrl-C and
+// the like to the child itself, since it's in the same process group, so only
+// signals that another process sent to pm are passed along. Otherwise the
+// child would get them twice.
+//
+// Without a child, the signal was meant for pm, so it gets what it would have
+// without this handler.
+extern "C" fn forward(signal: libc::c_int, info: *mut libc::siginfo_t, _: *mut libc::c_void) {
+    let child = CHILD.load(Ordering::SeqCst);
+    if child == 0 {
+        unsafe {
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+        return;
+    }
+    let from_process = unsafe { (*info).si_code <= 0 };
+    if from_process {
+        unsafe { libc::kill(child, signal) };
+    }
+}
+
+const FORWARDED: &[libc::c_int] = &[libc::SIGHUP, libc::SIGINT, libc::SIGQUIT, libc::SIGTERM, libc::SIGUSR1, libc::SIGUSR2, libc::SIGWINCH];
+
+// Sets up `forward` and returns what was there before, for `restore_signals`.
+fn forward_signals() -> Vec<(libc::c_int, libc::sigaction)> {
+    FORWARDED.iter()
+        .map(|signal| unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = forward as *const () as usize;
+            action.sa_flags = libc::SA_SIGINFO | libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            let mut previous: libc::sigaction = std::mem::zeroed();
+            libc::sigaction(*signal, &action, &mut previous);
+            (*signal, previous)
+        })
+        .collect()
+}
+
+fn restore_signals(previous: &[(libc::c_int, libc::sigaction)]) {
+    for (signal, action) in previous {
+        unsafe { libc::sigaction(*signal, action, std::ptr::null_mut()) };
+    }
+}
+
+// Runs the command with the variables added to its environment and returns
+// its exit code. The values only ever exist in memory and the child's
+// environment. The codes for when the command can't be run are the same as
+// env(1)'s: 127 when it isn't there and 126 when it can't be run. A command
+// killed by a signal gets 128 plus the signal, like in a shell.
+pub fn run(command: &[String], environment: Vec<(String, String)>) -> Result<i32, (i32, String)> {
+    let (program, args) = command.split_first().ok_or((125, String::from("Give me a command to run.")))?;
+    let previous = forward_signals();
+    let status = Command::new(program)
+        .args(args)
+        .envs(environment)
+        .spawn()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => (127, format!("{}: command not found", program)),
+            _ => (126, format!("Failed running {}: {}", program, e)),
+        })
+        .and_then(|mut child| {
+            CHILD.store(child.id() as i32, Ordering::SeqCst);
+            let status = child.wait();
+            // The pid could be someone else's after this.
+            CHILD.store(0, Ordering::SeqCst);
+            status.map_err(|e| (125, format!("Failed waiting for {}: {}", program, e)))
+        });
+    // The shell keeps going after this, and its signals are its own again.
+    restore_signals(&previous);
+    let status = status?;
+    Ok(status.code().or_else(|| status.signal().map(|signal| 128 + signal)).unwrap_or(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static S: fn(&'static str) -> String = String::from;
+
+    #[test]
+    fn test_variable() {
+        assert_eq!("AWS_SECRET=aws-prod:password".parse(), Ok(Variable{name: S("AWS_SECRET"), entry: S("aws-prod"), field: S("password")}));
+        assert_eq!("KEY=id:1a2b3c4d:username".parse(), Ok(Variable{name: S("KEY"), entry: S("id:1a2b3c4d"), field: S("username")}));
+        assert!("KEY".parse::<Variable>().is_err());
+        assert!("KEY=aws-prod".parse::<Variable>().is_err());
+        assert!("=aws-prod:password".parse::<Variable>().is_err());
+        assert!("KEY=aws-prod:".parse::<Variable>().is_err());
+    }
+
+    #[test]
+    fn test_environment() {
+        let entries = pm::Entries::new().update(S("aws-prod"), pm::Entry{
+            name: S("aws-prod"),
+            username: S("admin"),
+            password: S("hunter2"),
+            id: S("1a2b3c4d"),
+            fields: pm::Fields::new().update(S("region"), S("us-east-1")),
+            ..pm::Entry::default()
+        });
+        let variables: Vec<Variable> = ["SECRET=aws-prod:password", "USER=id:1a2b3c4d:username", "REGION=#1:region"]
+            .iter()
+            .map(|text| text.parse().unwrap())
+            .collect();
+        assert_eq!(environment(&entries, &variables), Ok(vec![
+            (S("SECRET"), S("hunter2")),
+            (S("USER"), S("admin")),
+            (S("REGION"), S("us-east-1")),
+        ]));
+        assert!(environment(&entries, &[S("X=aws-prod:nope").parse().unwrap()]).is_err());
+        assert!(environment(&entries, &[S("X=nope:password").parse().unwrap()]).is_err());
+    }
+
+    #[test]
+    fn test_run() {
+        let command = vec![S("sh"), S("-c"), S("test \"$SECRET\" = hunter2 && exit 3")];
+        assert_eq!(run(&command, vec![(S("SECRET"), S("hunter2"))]), Ok(3));
+        assert_eq!(run(&command, vec![]), Ok(1));
+        assert_eq!(run(&[S("sh"), S("-c"), S("kill -TERM $$")], vec![]), Ok(128 + libc::SIGTERM));
+        assert_eq!(run(&[S("/nonexistent/command")], vec![]).map_err(|(code, _)| code), Err(127));
+        assert_eq!(run(&[], vec![]).map_err(|(code, _)| code), Err(125));
+
+        // The handlers are gone afterward, so a TERM would stop pm again.
+        for signal in FORWARDED {
+            let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+            unsafe { libc::sigaction(*signal, std::ptr::null(), &mut action) };
+            assert_eq!(action.sa_sigaction, libc::SIG_DFL, "{}", signal);
+        }
+    }
+
+    #[test]
+    fn test_signal_without_child() {
+        // A signal for pm before there's a child isn't swallowed. The test
+        // runs itself again in another process to try it, so that it doesn't
+        // take the rest of the tests down with it.
+        if std::env::var_os("PM_TEST_SIGNAL").is_some() {
+            let previous = forward_signals();
+            unsafe { libc::raise(libc::SIGTERM) };
+            restore_signals(&previous);
+            return;
+        }
+        let status = Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "exec::tests::test_signal_without_child", "--test-threads=1"])
+            .env("PM_TEST_SIGNAL", "1")
+            .stdout(std::process::Stdio::null())
+            .status()
+            .unwrap();
+        assert_eq!(status.signal(), Some(libc::SIGTERM));
+    }
+}
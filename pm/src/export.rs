@@ -0,0 +1,173 @@
+use std::io::Write;
+
+use crate::Entry;
+
+// The formats that `pm export` can write.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    // A header row and then a row per entry.
+    Csv,
+    // An array with an object per entry.
+    Json,
+    // An object per entry, one per line.
+    Jsonl,
+    // A pass password store. See `crate::pass`.
+    Pass,
+}
+
+pub const FORMAT_NAMES: &[&str] = &["csv", "json", "jsonl", "pass"];
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Format, String> {
+        match name {
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            "jsonl" => Ok(Format::Jsonl),
+            "pass" => Ok(Format::Pass),
+            _ => Err(format!("\"{}\" isn't a format I can export. Try one of: {}.", name, FORMAT_NAMES.join(", "))),
+        }
+    }
+}
+
+// Whether a field could have something in it that shouldn't be shared.
+// Besides the password, notes and custom fields tend to pick up recovery
+// codes, TOTP secrets, and the like, so the only custom fields that are safe
+// are URLs.
+pub fn is_secret(field: &str) -> bool {
+    match field {
+        "name" | "username" | "id" | "tags" => false,
+        "password" | "notes" => true,
+        _ => !(field == "url" || field.starts_with("url ")),
+    }
+}
+
+// Works out which fields to export. Without a list, it's the usual fields,
+// the tags, and every custom field any of the entries has, leaving out the
+// secrets if `no_secrets` is set. Asking for a secret along with
+// `no_secrets` is an error.
+pub fn columns(entries: &[Entry], fields: Option<Vec<String>>, no_secrets: bool) -> Result<Vec<String>, String> {
+    match fields {
+        Some(fields) => {
+            if let Some(secret) = fields.iter().find(|field| no_secrets && is_secret(field)) {
+                return Err(format!("\"{}\" might have secrets in it, so it can't be exported with --no-secrets.", secret));
+            }
+            Ok(fields)
+        },
+        None => {
+            let custom: im::ordset::OrdSet<String> = entries.iter().flat_map(|entry| entry.fields.keys().cloned()).collect();
+            Ok(["name", "id", "username", "password", "notes", "tags"].iter()
+                .map(|field| field.to_string())
+                .chain(custom)
+                .filter(|field| !no_secrets || !is_secret(field))
+                .collect())
+        },
+    }
+}
+
+// Gets a field as text. Tags are separated by spaces, like they're typed in.
+fn value(entry: &Entry, column: &str) -> String {
+    match column {
+        "tags" => entry.tags.iter().cloned().collect::<Vec<_>>().join(" "),
+        _ => entry.field(column).unwrap_or("").to_owned(),
+    }
+}
+
+// Writes the entries out in one of the formats that go to a single file.
+pub fn write(format: Format, writer: &mut impl Write, entries: &[Entry], columns: &[String]) -> Result<(), String> {
+    match format {
+        Format::Csv => {
+            let mut csv_writer = csv::Writer::from_writer(writer);
+            csv_writer.write_record(columns).map_err(|e| format!("Failed writing the CSV: {}", e))?;
+            for entry in entries {
+                csv_writer.write_record(columns.iter().map(|column| value(entry, column)))
+                    .map_err(|e| format!("Failed writing the CSV: {}", e))?;
+            }
+            csv_writer.flush().map_err(|e| format!("Failed writing the CSV: {}", e))
+        },
+        Format::Json | Format::Jsonl => {
+            let objects: Vec<serde_json::Value> = entries.iter().map(|entry| object(entry, columns)).collect();
+            let text = if format == Format::Json {
+                format!("{}\n", serde_json::to_string_pretty(&objects).expect("Failed serializing entries."))
+            } else {
+                objects.iter().map(|object| format!("{}\n", object)).collect()
+            };
+            writer.write_all(text.as_bytes()).map_err(|e| format!("Failed writing the JSON: {}", e))
+        },
+        Format::Pass => Err(String::from("A password store is a directory. Use pass::write instead.")),
+    }
+}
+
+// Makes a JSON object out of the fields of an entry, in order. Tags are a
+// list here since JSON has them.
+fn object(entry: &Entry, columns: &[String]) -> serde_json::Value {
+    let mut object = serde_json::Map::new();
+    for column in columns {
+        let value = match column.as_str() {
+            "tags" => serde_json::Value::from(entry.tags.iter().cloned().collect::<Vec<_>>()),
+            _ => serde_json::Value::from(value(entry, column)),
+        };
+        object.insert(column.clone(), value);
+    }
+    serde_json::Value::Object(object)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Fields;
+
+    static S: fn(&'static str) -> String = String::from;
+
+    #[test]
+    fn test_columns() {
+        let entries = vec![Entry{
+            fields: Fields::new()
+                .update(S("url"), S("https://mail.example.com"))
+                .update(S("totp"), S("JBSWY3DPEHPK3PXP")),
+            ..Entry::default()
+        }];
+        assert_eq!(columns(&entries, None, false), Ok(vec![S("name"), S("id"), S("username"), S("password"), S("notes"), S("tags"), S("totp"), S("url")]));
+        assert_eq!(columns(&entries, None, true), Ok(vec![S("name"), S("id"), S("username"), S("tags"), S("url")]));
+        assert_eq!(columns(&entries, Some(vec![S("name"), S("url")]), true), Ok(vec![S("name"), S("url")]));
+        assert!(columns(&entries, Some(vec![S("name"), S("password")]), true).is_err());
+        assert!(columns(&entries, Some(vec![S("totp")]), true).is_err());
+        assert_eq!(columns(&entries, Some(vec![S("password")]), false), Ok(vec![S("password")]));
+    }
+
+    #[test]
+    fn test_write() {
+        let entries = vec![
+            Entry{
+                name: S("email"),
+                username: S("me@example.com"),
+                notes: S("first line\nsecond line"),
+                tags: crate::parse_tags("mail personal"),
+                fields: Fields::new().update(S("url"), S("https://mail.example.com")),
+                ..Entry::default()
+            },
+            Entry{name: S("work/wifi"), ..Entry::default()},
+        ];
+        let columns = vec![S("name"), S("username"), S("notes"), S("tags"), S("url")];
+
+        let mut writer: Vec<u8> = Vec::new();
+        write(Format::Csv, &mut writer, &entries, &columns).unwrap();
+        assert_eq!(std::str::from_utf8(&writer), Ok(concat!(
+            "name,username,notes,tags,url\n",
+            "email,me@example.com,\"first line\nsecond line\",mail personal,https://mail.example.com\n",
+            "work/wifi,,,,\n",
+        )));
+
+        let mut writer: Vec<u8> = Vec::new();
+        write(Format::Jsonl, &mut writer, &entries, &columns).unwrap();
+        assert_eq!(std::str::from_utf8(&writer), Ok(concat!(
+            r#"{"name":"email","username":"me@example.com","notes":"first line\nsecond line","tags":["mail","personal"],"url":"https://mail.example.com"}"#, "\n",
+            r#"{"name":"work/wifi","username":"","notes":"","tags":[],"url":""}"#, "\n",
+        )));
+
+        let mut writer: Vec<u8> = Vec::new();
+        write(Format::Json, &mut writer, &entries, &[S("name")]).unwrap();
+        assert_eq!(std::str::from_utf8(&writer), Ok("[\n  {\n    \"name\": \"email\"\n  },\n  {\n    \"name\": \"work/wifi\"\n  }\n]\n"));
+    }
+}
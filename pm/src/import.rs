@@ -0,0 +1,885 @@
+// Reads entries out of other password managers' exports. Each format gets a
+// function that turns the export into entries without looking at what's
+// already in the file; sorting out names that are already taken is up to the
+// caller (see `Collision`).
+
+use std::io::Read;
+
+use serde_json::Value;
+
+use crate::{Entry, Fields, Tags};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    KeepassXml,
+    BitwardenJson,
+    BitwardenCsv,
+    OnePassword1pux,
+    OnePasswordCsv,
+    BrowserCsv,
+    // A pass password store, which is a directory rather than a file. See
+    // `crate::pass`.
+    Pass,
+}
+
+pub const FORMAT_NAMES: &[&str] = &["keepass-xml", "bitwarden-json", "bitwarden-csv", "1password-1pux", "1password-csv", "browser-csv", "pass"];
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Format, String> {
+        match name {
+            "keepass-xml" => Ok(Format::KeepassXml),
+            "bitwarden-json" => Ok(Format::BitwardenJson),
+            "bitwarden-csv" => Ok(Format::BitwardenCsv),
+            "1password-1pux" => Ok(Format::OnePassword1pux),
+            "1password-csv" => Ok(Format::OnePasswordCsv),
+            "browser-csv" => Ok(Format::BrowserCsv),
+            "pass" => Ok(Format::Pass),
+            _ => Err(format!("\"{}\" isn't a format I can import. Try one of: {}.", name, FORMAT_NAMES.join(", "))),
+        }
+    }
+}
+
+// What was read out of an export. Most formats have one entry per item, but
+// some rows get merged into others or can't be used at all.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Imported {
+    pub entries: Vec<Entry>,
+    // How many rows were merged into an earlier one.
+    pub merged: usize,
+    // Why each row that was left out was left out.
+    pub skipped: Vec<String>,
+}
+
+impl From<Vec<Entry>> for Imported {
+    fn from(entries: Vec<Entry>) -> Imported {
+        Imported{entries, ..Imported::default()}
+    }
+}
+
+// The first bytes of a KeePass database, the encrypted .kdbx file.
+const KDBX_SIGNATURE: &[u8] = &[0x03, 0xd9, 0xa2, 0x9a, 0x67, 0xfb, 0x4b, 0xb5];
+
+// Reads an export. It takes bytes because a 1PUX file is a zip archive; the
+// rest are text.
+pub fn parse(format: Format, bytes: &[u8]) -> Result<Imported, String> {
+    if bytes.starts_with(KDBX_SIGNATURE) {
+        return Err(String::from("That's a KeePass database (.kdbx), which can't be read directly. Export it as KeePass XML from KeePass or KeePassXC first and import that with --format keepass-xml."));
+    }
+    let text = || std::str::from_utf8(bytes).map_err(|_| String::from("The file isn't UTF-8 text."));
+    match format {
+        Format::KeepassXml => keepass_xml(text()?).map(Imported::from),
+        Format::BitwardenJson => bitwarden_json(text()?).map(Imported::from),
+        Format::BitwardenCsv => bitwarden_csv(text()?).map(Imported::from),
+        Format::OnePassword1pux => onepassword_1pux(bytes).map(Imported::from),
+        Format::OnePasswordCsv => onepassword_csv(text()?).map(Imported::from),
+        Format::BrowserCsv => browser_csv(text()?),
+        Format::Pass => Err(String::from("A password store is a directory. Use pass::read instead.")),
+    }
+}
+
+// What to do with an imported entry whose name is already taken.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Collision {
+    // Ask each time.
+    Prompt,
+    // Add " (2)", " (3)", and so on until the name is free.
+    Suffix,
+    // Leave the existing entry alone and drop the imported one.
+    Skip,
+}
+
+impl std::str::FromStr for Collision {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Collision, String> {
+        match name {
+            "prompt" => Ok(Collision::Prompt),
+            "suffix" => Ok(Collision::Suffix),
+            "skip" => Ok(Collision::Skip),
+            _ => Err(format!("\"{}\" isn't a way to handle collisions. Try prompt, suffix, or skip.", name)),
+        }
+    }
+}
+
+// Finds a name like "name (2)" that isn't taken.
+pub fn suffixed(taken: impl Fn(&str) -> bool, name: &str) -> String {
+    (2..)
+        .map(|n| format!("{} ({})", name, n))
+        .find(|candidate| !taken(candidate))
+        .unwrap()
+}
+
+// Makes one part of an entry's name out of a title or group name. Slashes
+// separate folders, so they're swapped out of anything that isn't one.
+fn name_part(text: &str) -> String {
+    let part = text.trim().replace('/', "-");
+    if part.is_empty() {
+        String::from("(untitled)")
+    } else {
+        part
+    }
+}
+
+// Reads the XML that KeePass 2 and KeePassXC export (KeePassXC calls it
+// "KeePass XML"). The encrypted .kdbx file itself isn't supported, and
+// giving one says to export it first.
+//
+// Title, UserName, Password, and Notes fill in the entry. URL and any other
+// strings become custom fields, with URL as "url". Groups become folders,
+// leaving out the root group since every entry is in it. Old versions of an
+// entry (its History) and the recycle bin are skipped.
+pub fn keepass_xml(text: &str) -> Result<Vec<Entry>, String> {
+    let document = roxmltree::Document::parse(text)
+        .map_err(|e| format!("Failed reading the KeePass XML: {}", e))?;
+    let keepass = document.root_element();
+    if !keepass.has_tag_name("KeePassFile") {
+        return Err(String::from("That doesn't look like a KeePass XML export."));
+    }
+    let recycle_bin = child(keepass, "Meta")
+        .and_then(|meta| child_text(meta, "RecycleBinUUID"))
+        .unwrap_or_default();
+    let root_group = child(keepass, "Root")
+        .and_then(|root| child(root, "Group"))
+        .ok_or_else(|| String::from("The KeePass XML doesn't have any groups in it."))?;
+
+    let mut entries = Vec::new();
+    keepass_group(root_group, &[], &recycle_bin, &mut entries);
+    Ok(entries)
+}
+
+fn keepass_group(group: roxmltree::Node, folders: &[String], recycle_bin: &str, entries: &mut Vec<Entry>) {
+    for node in group.children().filter(|node| node.is_element()) {
+        if node.has_tag_name("Entry") {
+            entries.push(keepass_entry(node, folders));
+        } else if node.has_tag_name("Group") {
+            if !recycle_bin.is_empty() && child_text(node, "UUID").as_deref() == Some(recycle_bin) {
+                continue;
+            }
+            let mut subfolders = folders.to_vec();
+            subfolders.push(name_part(&child_text(node, "Name").unwrap_or_default()));
+            keepass_group(node, &subfolders, recycle_bin, entries);
+        }
+    }
+}
+
+fn keepass_entry(node: roxmltree::Node, folders: &[String]) -> Entry {
+    let mut entry = Entry::default();
+    let mut title = String::new();
+    for string in node.children().filter(|node| node.has_tag_name("String")) {
+        let key = child_text(string, "Key").unwrap_or_default();
+        let value = child_text(string, "Value").unwrap_or_default();
+        match key.as_str() {
+            "Title" => title = value,
+            "UserName" => entry.username = value,
+            "Password" => entry.password = value,
+            "Notes" => entry.notes = value,
+            _ if value.is_empty() => {},
+            "URL" => entry.fields = entry.fields.update(String::from("url"), value),
+            _ => entry.fields = entry.fields.update(key, value),
+        }
+    }
+    // KeePass separates tags with semicolons and KeePassXC with commas.
+    entry.tags = child_text(node, "Tags")
+        .unwrap_or_default()
+        .split([';', ','])
+        .flat_map(|tag| tag.split_whitespace())
+        .map(String::from)
+        .collect::<Tags>();
+    let mut path = folders.to_vec();
+    path.push(name_part(&title));
+    entry.name = path.join("/");
+    entry
+}
+
+// Reads an unencrypted Bitwarden JSON export.
+//
+// Logins fill in the username and password, with the URIs as "url", "url 2",
+// and so on and the TOTP secret as "totp". Cards use the cardholder as the
+// username and the number as the password, with the rest as fields. Custom
+// fields stay custom fields. Bitwarden's folders are already paths like
+// "Work/AWS", so they become folders as is. Anything else, like the details
+// of an identity, is added to the notes so that it isn't lost.
+pub fn bitwarden_json(text: &str) -> Result<Vec<Entry>, String> {
+    let export: Value = serde_json::from_str(text)
+        .map_err(|e| format!("Failed reading the Bitwarden JSON: {}", e))?;
+    if export["encrypted"] == Value::Bool(true) {
+        return Err(String::from("That Bitwarden export is encrypted. Export it again as unencrypted JSON."));
+    }
+    let items = export["items"].as_array()
+        .ok_or_else(|| String::from("That doesn't look like a Bitwarden JSON export."))?;
+    let folders: std::collections::HashMap<String, String> = export["folders"].as_array()
+        .map(|folders| folders.iter().map(|folder| (json_str(folder, "id"), json_str(folder, "name"))).collect())
+        .unwrap_or_default();
+
+    Ok(items.iter().map(|item| {
+        let mut entry = Entry{notes: json_str(item, "notes"), ..Entry::default()};
+        let mut unmapped = Vec::new();
+        let folder = item["folderId"].as_str().and_then(|id| folders.get(id)).cloned().unwrap_or_default();
+        entry.name = folder_path(&folder, &json_str(item, "name"));
+        if item["favorite"] == Value::Bool(true) {
+            entry.tags = entry.tags.update(String::from("favorite"));
+        }
+        match item["type"].as_u64() {
+            Some(1) => {
+                let login = &item["login"];
+                entry.username = json_str(login, "username");
+                entry.password = json_str(login, "password");
+                let uris = login["uris"].as_array().map(|uris| uris.iter().map(|uri| json_str(uri, "uri")).collect()).unwrap_or_default();
+                entry.fields = add_urls(entry.fields, uris);
+                entry.fields = add_field(entry.fields, "totp", json_str(login, "totp"));
+            },
+            Some(2) => {},
+            Some(3) => {
+                let card = &item["card"];
+                entry.username = json_str(card, "cardholderName");
+                entry.password = json_str(card, "number");
+                entry.fields = add_field(entry.fields, "brand", json_str(card, "brand"));
+                let expiry = match (json_str(card, "expMonth"), json_str(card, "expYear")) {
+                    (month, year) if month.is_empty() && year.is_empty() => String::new(),
+                    (month, year) => format!("{}/{}", month, year),
+                };
+                entry.fields = add_field(entry.fields, "expiry", expiry);
+                entry.fields = add_field(entry.fields, "code", json_str(card, "code"));
+            },
+            _ => {
+                // Identities and anything newer than this.
+                for key in &["identity", "sshKey"] {
+                    if let Some(object) = item[*key].as_object() {
+                        for (field, value) in object {
+                            unmapped.push((field.clone(), json_to_string(value)));
+                        }
+                    }
+                }
+            },
+        }
+        if let Some(fields) = item["fields"].as_array() {
+            for field in fields {
+                entry.fields = add_field(entry.fields, &json_str(field, "name"), json_str(field, "value"));
+            }
+        }
+        entry.notes = append_unmapped(&entry.notes, unmapped);
+        entry
+    }).collect())
+}
+
+// Reads a Bitwarden CSV export. It has less in it than the JSON: only logins
+// and secure notes, with custom fields as "name: value" lines.
+pub fn bitwarden_csv(text: &str) -> Result<Vec<Entry>, String> {
+    let known = ["folder", "favorite", "type", "name", "notes", "fields", "reprompt", "login_uri", "login_username", "login_password", "login_totp"];
+    read_csv(text, "name", |row| {
+        let mut entry = Entry{
+            name: folder_path(row.get("folder"), row.get("name")),
+            username: row.get("login_username").to_owned(),
+            password: row.get("login_password").to_owned(),
+            notes: row.get("notes").to_owned(),
+            ..Entry::default()
+        };
+        if row.get("favorite") == "1" {
+            entry.tags = entry.tags.update(String::from("favorite"));
+        }
+        // More than one URI is separated by commas.
+        entry.fields = add_urls(entry.fields, row.get("login_uri").split(',').map(String::from).collect());
+        entry.fields = add_field(entry.fields, "totp", row.get("login_totp").to_owned());
+        for line in row.get("fields").lines() {
+            let (name, value) = match line.find(": ") {
+                Some(i) => (&line[..i], &line[i + 2..]),
+                None => (line, ""),
+            };
+            entry.fields = add_field(entry.fields, name, value.to_owned());
+        }
+        entry.notes = append_unmapped(&entry.notes, row.others(&known));
+        entry
+    })
+}
+
+// Reads a 1Password 1PUX export, which is a zip archive with the items in
+// export.data as JSON. It's also fine to give it export.data on its own.
+//
+// Vaults become folders. Login fields fill in the username and password, and
+// the fields in each section become custom fields. For credit cards, the
+// cardholder and number are used as the username and password. Anything that
+// isn't a simple value, like an address, is added to the notes. The password
+// history is left behind.
+pub fn onepassword_1pux(bytes: &[u8]) -> Result<Vec<Entry>, String> {
+    let data = if bytes.starts_with(b"PK") {
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(bytes))
+            .map_err(|e| format!("Failed reading the 1PUX file: {}", e))?;
+        let mut file = archive.by_name("export.data")
+            .map_err(|_| String::from("The 1PUX file doesn't have export.data in it."))?;
+        let mut data = String::new();
+        file.read_to_string(&mut data).map_err(|e| format!("Failed reading the 1PUX file: {}", e))?;
+        data
+    } else {
+        String::from_utf8(bytes.to_vec()).map_err(|_| String::from("The file isn't a zip archive or UTF-8 text."))?
+    };
+    let export: Value = serde_json::from_str(&data)
+        .map_err(|e| format!("Failed reading the 1Password export: {}", e))?;
+    let accounts = export["accounts"].as_array()
+        .ok_or_else(|| String::from("That doesn't look like a 1Password 1PUX export."))?;
+
+    let mut entries = Vec::new();
+    for vault in accounts.iter().flat_map(|account| json_array(&account["vaults"])) {
+        let vault_name = json_str(&vault["attrs"], "name");
+        for item in json_array(&vault["items"]) {
+            let (overview, details) = (&item["overview"], &item["details"]);
+            let mut entry = Entry{
+                name: folder_path(&vault_name, &json_str(overview, "title")),
+                password: json_str(details, "password"),
+                notes: json_str(details, "notesPlain"),
+                tags: json_array(&overview["tags"]).iter().filter_map(|tag| tag.as_str()).flat_map(|tag| tag.split_whitespace()).map(String::from).collect(),
+                ..Entry::default()
+            };
+            if item["state"] == "archived" {
+                entry.tags = entry.tags.update(String::from("archived"));
+            }
+            for field in json_array(&details["loginFields"]) {
+                match field["designation"].as_str() {
+                    Some("username") => entry.username = json_str(field, "value"),
+                    Some("password") => entry.password = json_str(field, "value"),
+                    _ => {},
+                }
+            }
+            let mut urls = vec![json_str(overview, "url")];
+            urls.extend(json_array(&overview["urls"]).iter().map(|url| json_str(url, "url")));
+            entry.fields = add_urls(entry.fields, urls);
+            let mut unmapped = Vec::new();
+            for field in json_array(&details["sections"]).iter().flat_map(|section| json_array(&section["fields"])) {
+                let title = match json_str(field, "title") {
+                    ref title if title.is_empty() => json_str(field, "id"),
+                    title => title,
+                };
+                // The value is an object with one key saying what kind it is,
+                // like {"concealed": "..."}.
+                let value = match field["value"].as_object().and_then(|value| value.iter().next()) {
+                    None => continue,
+                    Some((kind, value)) if kind == "monthYear" => match value.as_u64() {
+                        Some(n) => format!("{:02}/{}", n % 100, n / 100),
+                        None => json_to_string(value),
+                    },
+                    Some((_, value)) if value.is_object() || value.is_array() => {
+                        unmapped.push((title, json_to_string(value)));
+                        continue;
+                    },
+                    Some((_, value)) => json_to_string(value),
+                };
+                if item["categoryUuid"] == "002" && field["id"] == "cardholder" {
+                    entry.username = value;
+                } else if item["categoryUuid"] == "002" && field["id"] == "ccnum" {
+                    entry.password = value;
+                } else {
+                    entry.fields = add_field(entry.fields, &title, value);
+                }
+            }
+            entry.notes = append_unmapped(&entry.notes, unmapped);
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+// Reads a 1Password CSV export. Which columns there are depends on the
+// version of 1Password, so they're matched by name and anything that isn't
+// recognized is added to the notes.
+pub fn onepassword_csv(text: &str) -> Result<Vec<Entry>, String> {
+    let known = ["title", "url", "username", "password", "otpauth", "favorite", "archived", "tags", "notes", "notesplain", "website", "name"];
+    read_csv(text, "title", |row| {
+        let mut entry = Entry{
+            name: name_part(row.first(&["title", "name"])),
+            username: row.get("username").to_owned(),
+            password: row.get("password").to_owned(),
+            notes: row.first(&["notes", "notesplain"]).to_owned(),
+            tags: row.get("tags").split([';', ',']).flat_map(|tag| tag.split_whitespace()).map(String::from).collect(),
+            ..Entry::default()
+        };
+        if row.get("favorite") == "true" {
+            entry.tags = entry.tags.update(String::from("favorite"));
+        }
+        if row.get("archived") == "true" {
+            entry.tags = entry.tags.update(String::from("archived"));
+        }
+        entry.fields = add_urls(entry.fields, vec![row.first(&["url", "website"]).to_owned()]);
+        entry.fields = add_field(entry.fields, "totp", row.get("otpauth").to_owned());
+        entry.notes = append_unmapped(&entry.notes, row.others(&known));
+        entry
+    })
+}
+
+// Reads the CSV that Chrome and Firefox export their saved passwords as. Chrome
+// has name, url, username, password, and sometimes note. Firefox has no name
+// but adds its own bookkeeping, which is left out.
+//
+// Entries are named after the site when there's no name. Browsers save a
+// login once per URL, so rows with the same site and username are merged into
+// one entry with each URL as a field. If they have different passwords, the
+// first one wins and the others are kept in the notes. Rows without a site or
+// a password are skipped.
+pub fn browser_csv(text: &str) -> Result<Imported, String> {
+    let ignored = ["name", "url", "username", "password", "note", "httprealm", "formactionorigin", "guid", "timecreated", "timelastused", "timepasswordchanged"];
+    let rows = read_csv(text, "url", |row| {
+        let entry = Entry{
+            name: if row.get("name").trim().is_empty() { String::new() } else { name_part(row.get("name")) },
+            username: row.get("username").to_owned(),
+            password: row.get("password").to_owned(),
+            notes: row.get("note").to_owned(),
+            ..Entry::default()
+        };
+        (entry, row.get("url").to_owned(), row.others(&ignored))
+    })?;
+
+    let mut imported = Imported::default();
+    // The site and username of each entry so far, to find ones to merge with.
+    let mut keys: Vec<(String, String)> = Vec::new();
+    for (i, (mut entry, url, others)) in rows.into_iter().enumerate() {
+        // The header is row 1.
+        let row = i + 2;
+        let site = site_of(&url);
+        if site.is_empty() {
+            imported.skipped.push(format!("row {}, which doesn't have a site", row));
+            continue;
+        }
+        if entry.password.is_empty() {
+            imported.skipped.push(format!("row {}, which doesn't have a password", row));
+            continue;
+        }
+        let key = (site.clone(), entry.username.clone());
+        match keys.iter().position(|k| *k == key) {
+            Some(existing) => {
+                let first = &mut imported.entries[existing];
+                first.fields = add_urls(first.fields.clone(), vec![url]);
+                let mut extra = Vec::new();
+                if entry.password != first.password {
+                    extra.push((String::from("other password"), entry.password));
+                }
+                if !entry.notes.is_empty() && !first.notes.contains(&entry.notes) {
+                    extra.push((String::from("other note"), entry.notes));
+                }
+                extra.extend(others);
+                first.notes = append_unmapped(&first.notes, extra);
+                imported.merged += 1;
+            },
+            None => {
+                if entry.name.is_empty() {
+                    entry.name = name_part(&site);
+                }
+                entry.fields = add_urls(entry.fields, vec![url]);
+                entry.notes = append_unmapped(&entry.notes, others);
+                keys.push(key);
+                imported.entries.push(entry);
+            },
+        }
+    }
+    Ok(imported)
+}
+
+// Gets the host out of a URL, without a leading "www.".
+fn site_of(url: &str) -> String {
+    let url = url.trim();
+    let rest = match url.find("://") {
+        Some(i) => &url[i + 3..],
+        None => url,
+    };
+    let host = rest.split(['/', '?', '#']).next().unwrap_or("");
+    // Drop any user info and port.
+    let host = host.rsplit('@').next().unwrap_or("");
+    let host = host.split(':').next().unwrap_or("");
+    host.strip_prefix("www.").unwrap_or(host).to_lowercase()
+}
+
+// A row of a CSV file with its headers, which are matched without regard to
+// case.
+struct Row<'a> {
+    headers: &'a [String],
+    values: csv::StringRecord,
+}
+
+impl<'a> Row<'a> {
+    fn get(&self, header: &str) -> &str {
+        self.headers.iter()
+            .position(|h| h == header)
+            .and_then(|i| self.values.get(i))
+            .unwrap_or("")
+    }
+
+    // Gets the first of these columns that has something in it.
+    fn first(&self, headers: &[&str]) -> &str {
+        headers.iter().map(|header| self.get(header)).find(|value| !value.is_empty()).unwrap_or("")
+    }
+
+    // Gets the columns that aren't in `known` and have something in them.
+    fn others(&self, known: &[&str]) -> Vec<(String, String)> {
+        self.headers.iter().zip(self.values.iter())
+            .filter(|(header, value)| !known.contains(&header.as_str()) && !value.is_empty())
+            .map(|(header, value)| (header.clone(), value.to_owned()))
+            .collect()
+    }
+}
+
+// Reads a CSV file with a header row, making something, usually an entry, out
+// of each row. `required` is a column that has to be there for it to be the
+// right kind of file.
+fn read_csv<T>(text: &str, required: &str, to_entry: impl Fn(&Row) -> T) -> Result<Vec<T>, String> {
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(text.as_bytes());
+    let headers: Vec<String> = reader.headers()
+        .map_err(|e| format!("Failed reading the CSV: {}", e))?
+        .iter()
+        .map(|header| header.trim().to_lowercase())
+        .collect();
+    if !headers.iter().any(|header| header == required) {
+        return Err(format!("The CSV doesn't have a \"{}\" column.", required));
+    }
+    reader.records()
+        .map(|record| {
+            let values = record.map_err(|e| format!("Failed reading the CSV: {}", e))?;
+            Ok(to_entry(&Row{headers: &headers, values}))
+        })
+        .collect()
+}
+
+// Makes a name out of a folder that might be a path, like "Work/AWS", and a
+// title.
+fn folder_path(folder: &str, title: &str) -> String {
+    folder.split('/')
+        .filter(|part| !part.trim().is_empty())
+        .map(name_part)
+        .chain(std::iter::once(name_part(title)))
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+// Adds a custom field, unless it's empty. If the name is taken, a number is
+// added to it.
+fn add_field(fields: Fields, name: &str, value: String) -> Fields {
+    let name = if name.trim().is_empty() { "field" } else { name.trim() };
+    if value.is_empty() {
+        fields
+    } else if !fields.contains_key(name) {
+        fields.update(name.to_owned(), value)
+    } else {
+        let free = (2..).map(|n| format!("{} {}", name, n)).find(|name| !fields.contains_key(name)).unwrap();
+        fields.update(free, value)
+    }
+}
+
+// Adds URLs as "url", "url 2", and so on, leaving out empty ones and repeats.
+fn add_urls(fields: Fields, urls: Vec<String>) -> Fields {
+    let mut seen = Vec::new();
+    urls.into_iter().map(|url| url.trim().to_owned()).fold(fields, |fields, url| {
+        if url.is_empty() || seen.contains(&url) {
+            fields
+        } else {
+            seen.push(url.clone());
+            add_field(fields, "url", url)
+        }
+    })
+}
+
+// Adds anything that didn't fit anywhere else to the end of the notes, one
+// "name: value" per line.
+fn append_unmapped(notes: &str, unmapped: Vec<(String, String)>) -> String {
+    let lines: Vec<String> = unmapped.into_iter()
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(name, value)| format!("{}: {}", name, value))
+        .collect();
+    match (notes.is_empty(), lines.is_empty()) {
+        (_, true) => notes.to_owned(),
+        (true, false) => lines.join("\n"),
+        (false, false) => format!("{}\n\n{}", notes, lines.join("\n")),
+    }
+}
+
+// Gets a string out of a JSON object. Missing and null are the same as empty.
+fn json_str(value: &Value, key: &str) -> String {
+    json_to_string(&value[key])
+}
+
+fn json_to_string(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(string) => string.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn json_array(value: &Value) -> &[Value] {
+    value.as_array().map(Vec::as_slice).unwrap_or(&[])
+}
+
+fn child<'a, 'input>(node: roxmltree::Node<'a, 'input>, name: &str) -> Option<roxmltree::Node<'a, 'input>> {
+    node.children().find(|child| child.has_tag_name(name))
+}
+
+fn child_text(node: roxmltree::Node, name: &str) -> Option<String> {
+    child(node, name).map(|child| child.text().unwrap_or_default().to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static S: fn(&'static str)->String = String::from;
+
+    const KEEPASS_XML: &str = r###"<?xml version="1.0" encoding="utf-8" standalone="yes"?>
+<KeePassFile>
+    <Meta>
+        <Generator>KeePassXC</Generator>
+        <RecycleBinUUID>cmVjeWNsZWJpbg==</RecycleBinUUID>
+    </Meta>
+    <Root>
+        <Group>
+            <UUID>cm9vdA==</UUID>
+            <Name>Passwords</Name>
+            <Entry>
+                <UUID>ZW1haWw=</UUID>
+                <Tags>personal;mail</Tags>
+                <String><Key>Notes</Key><Value>first line
+second line</Value></String>
+                <String><Key>Password</Key><Value ProtectInMemory="True">hunter2</Value></String>
+                <String><Key>Title</Key><Value>Email</Value></String>
+                <String><Key>URL</Key><Value>https://mail.example.com</Value></String>
+                <String><Key>UserName</Key><Value>me@example.com</Value></String>
+                <String><Key>Recovery code</Key><Value ProtectInMemory="True">abcd-efgh</Value></String>
+                <History>
+                    <Entry>
+                        <String><Key>Title</Key><Value>Email</Value></String>
+                        <String><Key>Password</Key><Value>old password</Value></String>
+                    </Entry>
+                </History>
+            </Entry>
+            <Group>
+                <UUID>d29yaw==</UUID>
+                <Name>Work</Name>
+                <Group>
+                    <UUID>YXdz</UUID>
+                    <Name>AWS</Name>
+                    <Entry>
+                        <String><Key>Title</Key><Value>prod/root</Value></String>
+                        <String><Key>UserName</Key><Value>root</Value></String>
+                        <String><Key>Password</Key><Value>correct horse</Value></String>
+                        <String><Key>URL</Key><Value></Value></String>
+                    </Entry>
+                </Group>
+            </Group>
+            <Group>
+                <UUID>cmVjeWNsZWJpbg==</UUID>
+                <Name>Recycle Bin</Name>
+                <Entry>
+                    <String><Key>Title</Key><Value>Deleted</Value></String>
+                </Entry>
+            </Group>
+        </Group>
+        <DeletedObjects/>
+    </Root>
+</KeePassFile>
+"###;
+
+    #[test]
+    fn test_keepass_xml() {
+        let entries = keepass_xml(KEEPASS_XML).unwrap();
+        assert_eq!(entries, vec![
+            Entry{
+                name: S("Email"),
+                username: S("me@example.com"),
+                password: S("hunter2"),
+                notes: S("first line\nsecond line"),
+                id: S(""),
+                tags: crate::parse_tags("mail personal"),
+                fields: Fields::new()
+                    .update(S("url"), S("https://mail.example.com"))
+                    .update(S("Recovery code"), S("abcd-efgh")),
+            },
+            Entry{
+                name: S("Work/AWS/prod-root"),
+                username: S("root"),
+                password: S("correct horse"),
+                notes: S(""),
+                id: S(""),
+                tags: Tags::new(),
+                fields: Fields::new(),
+            },
+        ]);
+
+        let kdbx = [KDBX_SIGNATURE, &[0x01, 0x00, 0x04, 0x00]].concat();
+        assert!(parse(Format::KeepassXml, &kdbx).unwrap_err().contains("Export it as KeePass XML"));
+        assert!(keepass_xml("<html></html>").is_err());
+        assert!(keepass_xml("not xml").is_err());
+    }
+
+    #[test]
+    fn test_suffixed() {
+        let taken = ["name", "name (2)"];
+        assert_eq!(suffixed(|name| taken.contains(&name), "name"), "name (3)");
+        assert_eq!(suffixed(|_| false, "other"), "other (2)");
+    }
+
+    fn by_name(entries: &[Entry], name: &str) -> Entry {
+        entries.iter().find(|entry| entry.name == name).unwrap().clone()
+    }
+
+    #[test]
+    fn test_bitwarden_json() {
+        let entries = parse(Format::BitwardenJson, include_bytes!("../fixtures/import/bitwarden.json")).unwrap().entries;
+        let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        // Duplicate titles are kept; the caller's collision policy sorts them
+        // out.
+        assert_eq!(names, vec!["Email", "Work/AWS/prod root", "Work/AWS/prod root", "Wifi", "Visa", "Passport"]);
+
+        assert_eq!(by_name(&entries, "Email"), Entry{
+            name: S("Email"),
+            username: S("me@example.com"),
+            password: S("hunter2"),
+            notes: S("first line\nsecond line"),
+            id: S(""),
+            tags: crate::parse_tags("favorite"),
+            fields: Fields::new()
+                .update(S("url"), S("https://mail.example.com"))
+                .update(S("url 2"), S("https://webmail.example.com"))
+                .update(S("totp"), S("otpauth://totp/Email?secret=JBSWY3DPEHPK3PXP"))
+                .update(S("Recovery code"), S("abcd-efgh")),
+        });
+        assert_eq!(entries[2].username, "admin");
+        assert_eq!(by_name(&entries, "Wifi").notes, "The password is on the router.");
+
+        let visa = by_name(&entries, "Visa");
+        assert_eq!((visa.username.as_str(), visa.password.as_str()), ("Jane Doe", "4111111111111111"));
+        assert_eq!(visa.field("expiry"), Some("12/2030"));
+        assert_eq!(visa.field("code"), Some("123"));
+
+        // Identities don't map to anything, so they end up in the notes.
+        let passport = by_name(&entries, "Passport");
+        assert!(passport.notes.contains("passportNumber: X1234567"));
+        assert!(passport.notes.contains("firstName: Jane"));
+        assert!(!passport.notes.contains("email"));
+
+        assert!(bitwarden_json(r###"{"encrypted": true, "data": "..."}"###).is_err());
+        assert!(bitwarden_json("{}").is_err());
+    }
+
+    #[test]
+    fn test_bitwarden_csv() {
+        let entries = parse(Format::BitwardenCsv, include_bytes!("../fixtures/import/bitwarden.csv")).unwrap().entries;
+        let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, vec!["Email", "Work/AWS/prod root", "Work/AWS/prod root", "Wifi"]);
+
+        assert_eq!(entries[0], Entry{
+            name: S("Email"),
+            username: S("me@example.com"),
+            password: S("hunter2"),
+            notes: S("first line\nsecond line"),
+            id: S(""),
+            tags: crate::parse_tags("favorite"),
+            fields: Fields::new()
+                .update(S("url"), S("https://mail.example.com"))
+                .update(S("totp"), S("otpauth://totp/Email?secret=JBSWY3DPEHPK3PXP"))
+                .update(S("Recovery code"), S("abcd-efgh")),
+        });
+        assert_eq!(entries[2].password, "battery staple");
+        assert_eq!(entries[3].notes, "The password is on the router.");
+
+        assert!(bitwarden_csv("Title,Password\nx,y\n").is_err());
+    }
+
+    #[test]
+    fn test_onepassword_1pux() {
+        let entries = parse(Format::OnePassword1pux, include_bytes!("../fixtures/import/1password.1pux")).unwrap().entries;
+        let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, vec!["Personal/Email", "Personal/Wifi", "Personal/Visa", "Work/prod root", "Work/prod root"]);
+
+        assert_eq!(entries[0], Entry{
+            name: S("Personal/Email"),
+            username: S("me@example.com"),
+            password: S("hunter2"),
+            notes: S("first line\nsecond line"),
+            id: S(""),
+            tags: crate::parse_tags("personal mail"),
+            fields: Fields::new()
+                .update(S("url"), S("https://mail.example.com"))
+                .update(S("Recovery code"), S("abcd-efgh"))
+                .update(S("one-time password"), S("otpauth://totp/Email?secret=JBSWY3DPEHPK3PXP")),
+        });
+        assert_eq!(entries[1].notes, "The password is on the router.");
+
+        let visa = &entries[2];
+        assert_eq!((visa.username.as_str(), visa.password.as_str()), ("Jane Doe", "4111111111111111"));
+        assert_eq!(visa.field("verification number"), Some("123"));
+        assert_eq!(visa.field("expiry date"), Some("12/2030"));
+
+        assert_eq!(entries[3].password, "correct horse");
+        // A tag can have spaces in it in 1Password, but not here.
+        assert_eq!(entries[3].tags, crate::parse_tags("work stuff aws"));
+        // A password item only has a password.
+        assert_eq!(entries[4].password, "battery staple");
+
+        assert!(onepassword_1pux(b"{}").is_err());
+        assert!(onepassword_1pux(b"PK not really a zip").is_err());
+    }
+
+    #[test]
+    fn test_onepassword_csv() {
+        let entries = parse(Format::OnePasswordCsv, include_bytes!("../fixtures/import/1password.csv")).unwrap().entries;
+        let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, vec!["Email", "prod root", "prod root"]);
+
+        assert_eq!(entries[0], Entry{
+            name: S("Email"),
+            username: S("me@example.com"),
+            password: S("hunter2"),
+            notes: S("first line\nsecond line"),
+            id: S(""),
+            tags: crate::parse_tags("favorite mail personal"),
+            fields: Fields::new()
+                .update(S("url"), S("https://mail.example.com"))
+                .update(S("totp"), S("otpauth://totp/Email?secret=JBSWY3DPEHPK3PXP")),
+        });
+        assert_eq!(entries[2].tags, crate::parse_tags("archived"));
+
+        // Columns that aren't recognized end up in the notes.
+        let entries = onepassword_csv("Title,Password,Security question\nBank,hunter2,Mother's maiden name\n").unwrap();
+        assert_eq!(entries[0].notes, "security question: Mother's maiden name");
+    }
+
+    #[test]
+    fn test_browser_csv() {
+        let imported = parse(Format::BrowserCsv, include_bytes!("../fixtures/import/chrome.csv")).unwrap();
+        let names: Vec<&str> = imported.entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, vec!["mail.example.com", "example.org", "example.org"]);
+        assert_eq!(imported.merged, 1);
+        assert_eq!(imported.skipped, vec![S("row 5, which doesn't have a password")]);
+
+        // The second mail.example.com row was merged into the first.
+        assert_eq!(imported.entries[0], Entry{
+            name: S("mail.example.com"),
+            username: S("me@example.com"),
+            password: S("hunter2"),
+            notes: S("other password: hunter3"),
+            id: S(""),
+            tags: Tags::new(),
+            fields: Fields::new()
+                .update(S("url"), S("https://mail.example.com/login"))
+                .update(S("url 2"), S("https://mail.example.com/signin?next=inbox")),
+        });
+        // Named after the site, since there's no name.
+        assert_eq!(imported.entries[1].notes, "the shared one");
+        assert_eq!(imported.entries[2].username, "admin");
+
+        let imported = parse(Format::BrowserCsv, include_bytes!("../fixtures/import/firefox.csv")).unwrap();
+        let names: Vec<&str> = imported.entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, vec!["mail.example.com", "accounts.example.com", "mail.example.com", "firefoxaccounts"]);
+        assert_eq!(imported.merged, 0);
+        assert_eq!(imported.skipped, vec![S("row 6, which doesn't have a site")]);
+        // Firefox's bookkeeping doesn't end up in the notes.
+        assert_eq!(imported.entries[0].notes, "");
+
+        assert!(browser_csv("name,username,password\nx,y,z\n").is_err());
+    }
+
+    #[test]
+    fn test_site_of() {
+        assert_eq!(site_of("https://www.Example.com/login?next=/"), "example.com");
+        assert_eq!(site_of("http://me@example.org:8080/admin"), "example.org");
+        assert_eq!(site_of("android://hash@com.example.app/"), "com.example.app");
+        assert_eq!(site_of("example.net"), "example.net");
+        assert_eq!(site_of(""), "");
+    }
+}
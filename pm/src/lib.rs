@@ -1,19 +1,44 @@
+use std::hash::{BuildHasher, Hasher};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+pub mod credential;
+pub mod document;
+pub mod environment;
+pub mod export;
+pub mod import;
+pub mod pass;
+pub mod share;
+pub mod ssh;
+pub mod template;
+pub mod vault;
+
 pub type Entries = im::ordmap::OrdMap<String, Entry>;
+pub type Tags = im::ordset::OrdSet<String>;
+pub type Fields = im::ordmap::OrdMap<String, String>;
 
 pub trait EntriesStuff {
     // Deserializes JSON.
     fn deserialize(json: &str) -> Self;
     // Loads and deserializes a file. Assumes the file exists because it will
     // be checked as part of the CLI application.
-    fn load(filename: &std::path::PathBuf) -> Self;
+    fn load(filename: &std::path::Path) -> Self;
 
     // Serializes into JSON.
     fn serialize(&self) -> String;
     // Serializes and writes a file.
-    fn save(&self, filename: &std::path::PathBuf) -> std::io::Result<()>;
+    fn save(&self, filename: &std::path::Path) -> std::io::Result<()>;
+
+    // Gets an entry by a selector. See `Selector` for what they look like.
+    fn getish(&self, selector: &str) -> Result<Entry, String>;
+
+    // Generates a short ID that isn't used by any of the entries.
+    fn new_id(&self) -> String;
+    // Gives an ID to every entry that doesn't have one. Files written before
+    // entries had IDs are migrated this way.
+    fn assign_ids(&self) -> Self;
 
-    // Gets an entry by its name or index.
-    fn getish(&self, name_or_index: &str) -> Result<Entry, String>;
+    // Counts how many entries have each tag.
+    fn tag_counts(&self) -> im::ordmap::OrdMap<String, usize>;
 }
 
 impl EntriesStuff for Entries {
@@ -26,39 +51,242 @@ impl EntriesStuff for Entries {
         x
     }
 
-    fn save(&self, filename: &std::path::PathBuf) -> std::io::Result<()> {
+    fn save(&self, filename: &std::path::Path) -> std::io::Result<()> {
         std::fs::write(filename, self.serialize())
     }
 
-    fn load(filename: &std::path::PathBuf) -> Self {
+    fn load(filename: &std::path::Path) -> Self {
         let bytes = &std::fs::read(filename).expect("Failed reading file.");
         let string = std::str::from_utf8(bytes)
             .expect("Failed casting from bytes to a string. I'm not sure I need to do this, even, but I wanted to keep going after I got it to work.");
         Self::deserialize(string)
     }
 
-    fn getish(&self, name_or_index: &str) -> Result<Entry, String> {
-        match name_or_index.parse::<usize>() {
-            Ok(index) => match self.clone().keys().nth(index - 1) {
-                None => Err(String::from("No entry at that index.")),
-                Some(name) => Ok(self.get(name).unwrap().clone()),
+    fn getish(&self, selector: &str) -> Result<Entry, String> {
+        match Selector::parse(selector)? {
+            Selector::Index(index) => by_index(self, index),
+            Selector::Id(id) => by_id(self, &id),
+            Selector::Name(name) => by_name(self, &name),
+            Selector::Any(text) => {
+                // IDs are checked first because they never change. An index
+                // is only good until something that sorts before it is added,
+                // so it's the last resort.
+                by_id(self, &text)
+                    .or_else(|_| by_name(self, &text))
+                    .or_else(|_| match text.parse::<usize>() {
+                        Ok(index) => by_index(self, index),
+                        Err(_) => Err(format!("No entry with the ID or name \"{}\".", text)),
+                    })
             },
-            Err(_) => {
-                match self.get(name_or_index) {
-                    None => Err(String::from("No entry by that name.")),
-                    Some(entry) => Ok(entry.clone()),
-                }
+        }
+    }
+
+    fn new_id(&self) -> String {
+        loop {
+            // Every RandomState is seeded differently, so hashing anything
+            // with a new one gives me a random number without pulling in a
+            // crate for it.
+            let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+            hasher.write_usize(self.len());
+            let id = format!("{:08x}", hasher.finish() as u32);
+            if !self.values().any(|entry| entry.id == id) {
+                break id;
+            }
+        }
+    }
+
+    fn assign_ids(&self) -> Self {
+        self.iter().fold(self.clone(), |entries, (key, entry)| {
+            if entry.id.is_empty() {
+                let id = entries.new_id();
+                entries.update(key.clone(), Entry{id, ..entry.clone()})
+            } else {
+                entries
+            }
+        })
+    }
+
+    fn tag_counts(&self) -> im::ordmap::OrdMap<String, usize> {
+        self.values()
+            .flat_map(|entry| entry.tags.iter())
+            .fold(im::ordmap::OrdMap::new(), |counts, tag| {
+                let count = counts.get(tag).cloned().unwrap_or(0);
+                counts.update(tag.clone(), count + 1)
+            })
+    }
+}
+
+// Splits user input like "work prod" into tags.
+pub fn parse_tags(text: &str) -> Tags {
+    text.split_whitespace().map(String::from).collect()
+}
+
+// Names can be paths like "work/aws/prod-root" to put entries in folders.
+// They're still just keys in `Entries`, so a flat name is an entry at the top.
+// Checks that there's no empty folder or name in the path.
+pub fn check_name(name: &str) -> Result<(), String> {
+    if name.split('/').any(|segment| segment.is_empty()) {
+        Err(format!("\"{}\" isn't a valid name. Folders are separated by a single slash and a name can't start or end with one.", name))
+    } else {
+        Ok(())
+    }
+}
+
+// A tag is one word, since tags are typed separated by spaces.
+pub fn check_tag(tag: &str) -> Result<(), String> {
+    if parse_tags(tag) != Tags::singleton(String::from(tag)) {
+        Err(format!("\"{}\" isn't a tag. A tag can't be empty or have spaces in it.", tag))
+    } else {
+        Ok(())
+    }
+}
+
+// Creates a file that only we can read, for anything that could be full of
+// passwords. `new` refuses to replace one that's already there. The mode only
+// counts when the file is created, so one that's being replaced is changed
+// to match.
+pub fn create_private(path: &std::path::Path, new: bool) -> Result<std::fs::File, String> {
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .create_new(new)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .map_err(|e| format!("Failed creating {}: {}", path.display(), e))?;
+    file.set_permissions(std::fs::Permissions::from_mode(0o600))
+        .map_err(|e| format!("Failed making {} private: {}", path.display(), e))?;
+    Ok(file)
+}
+
+// Gets the folder part of a name, without the trailing slash. It's empty for
+// entries at the top.
+pub fn folder_of(name: &str) -> &str {
+    match name.rfind('/') {
+        None => "",
+        Some(i) => &name[..i],
+    }
+}
+
+// Gets the name without its folder.
+pub fn leaf_of(name: &str) -> &str {
+    match name.rfind('/') {
+        None => name,
+        Some(i) => &name[i + 1..],
+    }
+}
+
+// Which entries a command like `list` should include. An entry has to be in
+// `folder` (or one under it), have all of `all_tags` and, if there are any, at
+// least one of `any_tags`. If there's a `search`, its name or username has to
+// have it in it, ignoring case.
+#[derive(Debug, Default, PartialEq)]
+pub struct Filter {
+    pub folder: String,
+    pub all_tags: Vec<String>,
+    pub any_tags: Vec<String>,
+    pub search: String,
+}
+
+impl Filter {
+    pub fn matches(&self, entry: &Entry) -> bool {
+        let folder = self.folder.trim_matches('/');
+        let search = self.search.to_lowercase();
+        (folder.is_empty() || entry.name.starts_with(&format!("{}/", folder)))
+            && self.all_tags.iter().all(|tag| entry.tags.contains(tag))
+            && (self.any_tags.is_empty() || self.any_tags.iter().any(|tag| entry.tags.contains(tag)))
+            && (search.is_empty() || entry.name.to_lowercase().contains(&search) || entry.username.to_lowercase().contains(&search))
+    }
+}
+
+// How an entry is picked out on the command line.
+//
+//     #3          the third entry, as numbered by `list`
+//     id:1a2b3c4d the entry with that ID
+//     =1234       the entry named "1234", even though it looks like an index
+//
+// Anything else is tried as an ID, then as a name, and then, if it's a
+// number, as an index. The prefixes are for when that guess is wrong, like an
+// entry named after a door code.
+#[derive(Debug, PartialEq)]
+pub enum Selector {
+    Index(usize),
+    Id(String),
+    Name(String),
+    Any(String),
+}
+
+impl Selector {
+    pub fn parse(text: &str) -> Result<Selector, String> {
+        if let Some(index) = text.strip_prefix('#') {
+            match index.parse::<usize>() {
+                Ok(index) => Ok(Selector::Index(index)),
+                Err(_) => Err(format!("\"{}\" isn't an index. An index looks like #3.", text)),
             }
+        } else if let Some(id) = text.strip_prefix("id:") {
+            Ok(Selector::Id(id.to_owned()))
+        } else if let Some(name) = text.strip_prefix('=') {
+            Ok(Selector::Name(name.to_owned()))
+        } else {
+            Ok(Selector::Any(text.to_owned()))
         }
     }
 }
 
-#[derive(serde_derive::Serialize, serde_derive::Deserialize, Debug)]
+fn by_index(entries: &Entries, index: usize) -> Result<Entry, String> {
+    if index == 0 {
+        return Err(String::from("Indices start at 1."));
+    }
+    match entries.values().nth(index - 1) {
+        None => Err(format!("No entry at index {}. There are only {}.", index, entries.len())),
+        Some(entry) => Ok(entry.clone()),
+    }
+}
+
+fn by_id(entries: &Entries, id: &str) -> Result<Entry, String> {
+    match entries.values().find(|entry| entry.id == id) {
+        None => Err(format!("No entry with the ID \"{}\".", id)),
+        Some(entry) => Ok(entry.clone()),
+    }
+}
+
+fn by_name(entries: &Entries, name: &str) -> Result<Entry, String> {
+    match entries.get(name) {
+        None => Err(format!("No entry by the name \"{}\".", name)),
+        Some(entry) => Ok(entry.clone()),
+    }
+}
+
+#[derive(serde_derive::Serialize, serde_derive::Deserialize, Debug, Default)]
 pub struct Entry {
     pub name: String,
     pub username: String,
     pub password: String,
     pub notes: String,
+    // A short, random ID that stays the same for the life of the entry. It's
+    // missing in files from before IDs existed; see `assign_ids`.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub id: String,
+    #[serde(default, skip_serializing_if = "im::ordset::OrdSet::is_empty")]
+    pub tags: Tags,
+    // Anything else worth keeping, like a PIN or security questions.
+    #[serde(default, skip_serializing_if = "im::ordmap::OrdMap::is_empty")]
+    pub fields: Fields,
+}
+
+impl Entry {
+    // Gets a field by name. The usual fields win over custom ones with the
+    // same name.
+    pub fn field(&self, name: &str) -> Option<&str> {
+        match name {
+            "name" => Some(&self.name),
+            "username" => Some(&self.username),
+            "password" => Some(&self.password),
+            "notes" => Some(&self.notes),
+            "id" => Some(&self.id),
+            _ => self.fields.get(name).map(String::as_str),
+        }
+    }
 }
 
 impl Clone for Entry {
@@ -68,6 +296,9 @@ impl Clone for Entry {
             username: self.username.clone(),
             password: self.password.clone(),
             notes: self.notes.clone(),
+            id: self.id.clone(),
+            tags: self.tags.clone(),
+            fields: self.fields.clone(),
         }
     }
 }
@@ -78,12 +309,16 @@ impl PartialEq for Entry {
         && self.username == other.username
         && self.password == other.password
         && self.notes == other.notes
+        && self.id == other.id
+        && self.tags == other.tags
+        && self.fields == other.fields
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::io::Write;
 
     static S: fn(&'static str)->String = String::from;
 
@@ -94,6 +329,9 @@ mod tests {
             username: S("username"),
             password: S("password"),
             notes: S("notes"),
+            id: S("id"),
+            tags: Tags::new(),
+            fields: Fields::new(),
         };
         assert_eq!(original, original.clone());
     }
@@ -110,8 +348,11 @@ mod tests {
             username: S("First Username"),
             password: S("First Password"),
             notes: S("First Notes"),
+            id: S("first"),
+            tags: Tags::new(),
+            fields: Fields::new(),
         });
-        let btext = r###"{"First":{"name":"First","username":"First Username","password":"First Password","notes":"First Notes"}}"###;
+        let btext = r###"{"First":{"name":"First","username":"First Username","password":"First Password","notes":"First Notes","id":"first"}}"###;
         assert_eq!(b.serialize(), btext);
         assert_eq!(Entries::deserialize(&b.serialize()), b);
 
@@ -122,8 +363,11 @@ mod tests {
             username: S("2nd Username"),
             password: S("2nd Password"),
             notes: S("2nd Notes"),
+            id: S("2nd"),
+            tags: Tags::new(),
+            fields: Fields::new(),
         });
-        let ctext = r###"{"2nd":{"name":"2nd","username":"2nd Username","password":"2nd Password","notes":"2nd Notes"},"First":{"name":"First","username":"First Username","password":"First Password","notes":"First Notes"}}"###;
+        let ctext = r###"{"2nd":{"name":"2nd","username":"2nd Username","password":"2nd Password","notes":"2nd Notes","id":"2nd"},"First":{"name":"First","username":"First Username","password":"First Password","notes":"First Notes","id":"first"}}"###;
         assert_eq!(c.serialize(), ctext);
         assert_eq!(Entries::deserialize(&c.serialize()), c);
     }
@@ -135,11 +379,17 @@ mod tests {
             username: S("First Username"),
             password: S("First Password"),
             notes: S("First Notes"),
+            id: S("first"),
+            tags: Tags::new(),
+            fields: Fields::new(),
         }).update(S("second"), Entry{
             name: S("Second"),
             username: S("Second Username"),
             password: S("Second Password"),
             notes: S("Second Notes"),
+            id: S("second"),
+            tags: Tags::new(),
+            fields: Fields::new(),
         });
 
         let filename = mktemp::Temp::new_file().unwrap().to_path_buf();
@@ -156,11 +406,17 @@ mod tests {
             username: S("First Username"),
             password: S("First Password"),
             notes: S("First Notes"),
+            id: S("first"),
+            tags: Tags::new(),
+            fields: Fields::new(),
         }).update(S("second"), Entry{
             name: S("Second"),
             username: S("Second Username"),
             password: S("Second Password"),
             notes: S("Second Notes"),
+            id: S("second"),
+            tags: Tags::new(),
+            fields: Fields::new(),
         });
 
         assert_eq!(S("First"), entries.getish("1").unwrap().name);
@@ -168,4 +424,226 @@ mod tests {
         assert_eq!(S("Second"), entries.getish("2").unwrap().name);
         assert_eq!(S("Second"), entries.getish("second").unwrap().name);
     }
+
+    #[test]
+    fn entries_getish_by_id() {
+        let entries = Entries::new().update(S("b"), Entry{
+            name: S("b"),
+            username: S("b username"),
+            password: S("b password"),
+            notes: S("b notes"),
+            id: S("0000000b"),
+            tags: Tags::new(),
+            fields: Fields::new(),
+        });
+        assert_eq!(S("b"), entries.getish("0000000b").unwrap().name);
+
+        // Adding an entry that sorts first changes the index but not the ID.
+        let entries = entries.update(S("a"), Entry{
+            name: S("a"),
+            username: S("a username"),
+            password: S("a password"),
+            notes: S("a notes"),
+            id: S("0000000a"),
+            tags: Tags::new(),
+            fields: Fields::new(),
+        });
+        assert_eq!(S("a"), entries.getish("1").unwrap().name);
+        assert_eq!(S("b"), entries.getish("0000000b").unwrap().name);
+    }
+
+    #[test]
+    fn selector_parse() {
+        assert_eq!(Selector::parse("#3"), Ok(Selector::Index(3)));
+        assert_eq!(Selector::parse("#0"), Ok(Selector::Index(0)));
+        assert!(Selector::parse("#three").is_err());
+        assert!(Selector::parse("#").is_err());
+        assert!(Selector::parse("#-1").is_err());
+        assert_eq!(Selector::parse("id:1a2b3c4d"), Ok(Selector::Id(S("1a2b3c4d"))));
+        assert_eq!(Selector::parse("=1234"), Ok(Selector::Name(S("1234"))));
+        assert_eq!(Selector::parse("=#3"), Ok(Selector::Name(S("#3"))));
+        assert_eq!(Selector::parse("1234"), Ok(Selector::Any(S("1234"))));
+        assert_eq!(Selector::parse("name"), Ok(Selector::Any(S("name"))));
+    }
+
+    #[test]
+    fn entries_getish_selectors() {
+        let entries = Entries::new().update(S("1234"), Entry{
+            name: S("1234"),
+            username: S("door username"),
+            password: S("door password"),
+            notes: S("door notes"),
+            id: S("d00d"),
+            tags: Tags::new(),
+            fields: Fields::new(),
+        }).update(S("2"), Entry{
+            name: S("2"),
+            username: S("two username"),
+            password: S("two password"),
+            notes: S("two notes"),
+            id: S("beef"),
+            tags: Tags::new(),
+            fields: Fields::new(),
+        }).update(S("mail"), Entry{
+            name: S("mail"),
+            username: S("mail username"),
+            password: S("mail password"),
+            notes: S("mail notes"),
+            id: S("1"),
+            tags: Tags::new(),
+            fields: Fields::new(),
+        });
+
+        // Explicit selectors.
+        assert_eq!(S("1234"), entries.getish("#1").unwrap().name);
+        assert_eq!(S("2"), entries.getish("#2").unwrap().name);
+        assert_eq!(S("mail"), entries.getish("#3").unwrap().name);
+        assert_eq!(S("2"), entries.getish("id:beef").unwrap().name);
+        assert_eq!(S("1234"), entries.getish("=1234").unwrap().name);
+        assert_eq!(S("2"), entries.getish("=2").unwrap().name);
+
+        // Bare selectors try the ID, then the name, then the index.
+        assert_eq!(S("mail"), entries.getish("1").unwrap().name);
+        assert_eq!(S("1234"), entries.getish("1234").unwrap().name);
+        assert_eq!(S("2"), entries.getish("2").unwrap().name);
+        assert_eq!(S("mail"), entries.getish("3").unwrap().name);
+
+        // Zero and out of range are errors instead of panics.
+        assert_eq!(entries.getish("#0"), Err(S("Indices start at 1.")));
+        assert_eq!(entries.getish("0"), Err(S("Indices start at 1.")));
+        assert_eq!(entries.getish("#4"), Err(S("No entry at index 4. There are only 3.")));
+        assert_eq!(entries.getish("99"), Err(S("No entry at index 99. There are only 3.")));
+        assert!(entries.getish("#nope").is_err());
+        assert!(entries.getish("id:1234").is_err());
+        assert!(entries.getish("=1").is_err());
+        assert!(entries.getish("nope").is_err());
+    }
+
+    #[test]
+    fn entries_assign_ids() {
+        // This is what a file looked like before entries had IDs.
+        let old = Entries::deserialize(r###"{"one":{"name":"one","username":"","password":"","notes":""},"two":{"name":"two","username":"","password":"","notes":"","id":"keepthis"}}"###);
+        assert_eq!(old.get("one").unwrap().id, "");
+
+        let migrated = old.assign_ids();
+        let one = &migrated.get("one").unwrap().id;
+        assert_eq!(one.len(), 8);
+        assert_eq!(migrated.get("two").unwrap().id, "keepthis");
+
+        // Migrating again doesn't change anything.
+        assert_eq!(migrated.assign_ids(), migrated);
+        assert_ne!(&migrated.new_id(), one);
+    }
+
+    #[test]
+    fn entries_tag_counts_and_filter() {
+        let entries = Entries::new().update(S("a"), Entry{
+            name: S("a"),
+            username: S("a username"),
+            password: S("a password"),
+            notes: S("a notes"),
+            id: S("a"),
+            tags: parse_tags("work prod"),
+            fields: Fields::new(),
+        }).update(S("b"), Entry{
+            name: S("b"),
+            username: S("b username"),
+            password: S("b password"),
+            notes: S("b notes"),
+            id: S("b"),
+            tags: parse_tags("work dev"),
+            fields: Fields::new(),
+        });
+
+        let counts = entries.tag_counts();
+        assert_eq!(counts.get("work"), Some(&2));
+        assert_eq!(counts.get("prod"), Some(&1));
+        assert_eq!(counts.get("dev"), Some(&1));
+
+        let a = entries.get("a").unwrap();
+        let b = entries.get("b").unwrap();
+        let all = Filter{folder: S(""), all_tags: vec![S("work"), S("prod")], any_tags: vec![], search: S("")};
+        assert!(all.matches(a));
+        assert!(!all.matches(b));
+        let any = Filter{folder: S(""), all_tags: vec![], any_tags: vec![S("prod"), S("dev")], search: S("")};
+        assert!(any.matches(a));
+        let search = Filter{search: S("B USER"), ..Filter::default()};
+        assert!(!search.matches(a));
+        assert!(search.matches(b));
+        assert!(any.matches(b));
+        assert!(Filter::default().matches(a));
+
+        // Tags are left out of the JSON when there aren't any.
+        let untagged = Entry{tags: Tags::new(), ..a.clone()};
+        assert!(!serde_json::to_string(&untagged).unwrap().contains("tags"));
+        assert!(serde_json::to_string(a).unwrap().contains(r###""tags":["prod","work"]"###));
+    }
+
+    #[test]
+    fn test_create_private() {
+        let temp = mktemp::Temp::new_dir().unwrap();
+        let file = temp.join("out");
+        std::fs::write(&file, "old").unwrap();
+        std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(create_private(&file, true).is_err());
+        create_private(&file, false).unwrap().write_all(b"new").unwrap();
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "new");
+        assert_eq!(std::fs::metadata(&file).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+
+    #[test]
+    fn names_as_paths() {
+        assert!(check_name("flat").is_ok());
+        assert!(check_name("work/aws/prod-root").is_ok());
+        assert!(check_name("").is_err());
+        assert!(check_name("/work").is_err());
+        assert!(check_name("work/").is_err());
+        assert!(check_name("work//aws").is_err());
+
+        assert!(check_tag("prod").is_ok());
+        assert!(check_tag("").is_err());
+        assert!(check_tag("two words").is_err());
+        assert!(check_tag(" prod").is_err());
+
+        assert_eq!(folder_of("work/aws/prod-root"), "work/aws");
+        assert_eq!(leaf_of("work/aws/prod-root"), "prod-root");
+        assert_eq!(folder_of("flat"), "");
+        assert_eq!(leaf_of("flat"), "flat");
+
+        let entry = Entry{
+            name: S("work/aws/prod-root"),
+            username: S("username"),
+            password: S("password"),
+            notes: S("notes"),
+            id: S("id"),
+            tags: Tags::new(),
+            fields: Fields::new(),
+        };
+        let folder = |folder: &str| Filter{folder: folder.to_owned(), all_tags: vec![], any_tags: vec![], search: S("")};
+        assert!(folder("").matches(&entry));
+        assert!(folder("work").matches(&entry));
+        assert!(folder("work/aws/").matches(&entry));
+        assert!(!folder("work/aws/prod-root").matches(&entry));
+        assert!(!folder("wor").matches(&entry));
+        assert!(!folder("home").matches(&entry));
+    }
+
+    #[test]
+    fn entry_field() {
+        let entry = Entry{
+            name: S("name"),
+            username: S("username"),
+            password: S("password"),
+            notes: S("notes"),
+            id: S("id"),
+            tags: Tags::new(),
+            fields: Fields::new().update(S("pin"), S("1234")).update(S("password"), S("not this one")),
+        };
+        assert_eq!(entry.field("username"), Some("username"));
+        assert_eq!(entry.field("password"), Some("password"));
+        assert_eq!(entry.field("notes"), Some("notes"));
+        assert_eq!(entry.field("pin"), Some("1234"));
+        assert_eq!(entry.field("nope"), None);
+        assert!(serde_json::to_string(&entry).unwrap().contains(r###""fields":{"password":"not this one","pin":"1234"}"###));
+    }
 }
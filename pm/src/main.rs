@@ -1,4 +1,10 @@
+mod agent;
 mod cli;
+mod clipboards;
+mod exec;
+mod server;
+mod shell;
+mod tui;
 
 fn main() {
     cli::run();
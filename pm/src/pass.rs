@@ -0,0 +1,334 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::{Entries, Entry};
+
+// How the files in a password store are read and written.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Crypt {
+    // With gpg, or a program that takes the same arguments.
+    Gpg(String),
+    // As plain text. It's for tests, which can't count on having gpg and a
+    // key around.
+    Plain,
+}
+
+impl Crypt {
+    fn decrypt(&self, path: &Path) -> Result<String, String> {
+        match self {
+            Crypt::Plain => std::fs::read_to_string(path)
+                .map_err(|e| format!("Failed reading {}: {}", path.display(), e)),
+            Crypt::Gpg(program) => {
+                // stderr is left alone so that gpg can ask for a passphrase.
+                let output = Command::new(program)
+                    .args(["--quiet", "--yes", "--decrypt"])
+                    .arg(path)
+                    .stdin(Stdio::null())
+                    .stderr(Stdio::inherit())
+                    .output()
+                    .map_err(|e| format!("Failed running {}: {}", program, e))?;
+                if !output.status.success() {
+                    return Err(format!("{} couldn't decrypt {}.", program, path.display()));
+                }
+                String::from_utf8(output.stdout).map_err(|_| format!("{} isn't UTF-8 text.", path.display()))
+            },
+        }
+    }
+
+    fn encrypt(&self, path: &Path, recipients: &[String], text: &str) -> Result<(), String> {
+        match self {
+            Crypt::Plain => std::fs::write(path, text)
+                .map_err(|e| format!("Failed writing {}: {}", path.display(), e)),
+            Crypt::Gpg(program) => {
+                // These are the same options pass uses.
+                let mut child = Command::new(program)
+                    .args(["--quiet", "--yes", "--batch", "--compress-algo=none", "--no-encrypt-to", "--encrypt", "--output"])
+                    .arg(path)
+                    .args(recipients.iter().flat_map(|recipient| vec!["--recipient", recipient]))
+                    .stdin(Stdio::piped())
+                    .spawn()
+                    .map_err(|e| format!("Failed running {}: {}", program, e))?;
+                child.stdin.take().unwrap().write_all(text.as_bytes())
+                    .map_err(|e| format!("Failed writing to {}: {}", program, e))?;
+                let status = child.wait().map_err(|e| format!("Failed running {}: {}", program, e))?;
+                if status.success() {
+                    Ok(())
+                } else {
+                    Err(format!("{} couldn't encrypt {}.", program, path.display()))
+                }
+            },
+        }
+    }
+}
+
+// Reads every entry in a password store. The folders in the store become
+// folders in the names, so "work/email.gpg" is "work/email". Anything hidden,
+// like .git and .gpg-id, is left alone.
+pub fn read(store: &Path, crypt: &Crypt) -> Result<Vec<Entry>, String> {
+    if !store.is_dir() {
+        return Err(format!("{} isn't a directory.", store.display()));
+    }
+    let mut entries = Vec::new();
+    for path in files(store)? {
+        let relative = path.strip_prefix(store).unwrap().with_extension("");
+        let name = relative.components()
+            .map(|part| part.as_os_str().to_string_lossy().into_owned())
+            .collect::<Vec<_>>()
+            .join("/");
+        entries.push(parse_file(&name, &crypt.decrypt(&path)?));
+    }
+    Ok(entries)
+}
+
+// Finds the .gpg files under a directory, sorted so that the order doesn't
+// depend on the file system.
+fn files(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let mut found = Vec::new();
+    let mut children = std::fs::read_dir(dir)
+        .and_then(|children| children.map(|child| child.map(|child| child.path())).collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("Failed reading {}: {}", dir.display(), e))?;
+    children.sort();
+    for child in children {
+        if child.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.')) {
+            continue;
+        }
+        if child.is_dir() {
+            found.extend(files(&child)?);
+        } else if child.extension().is_some_and(|extension| extension == "gpg") {
+            found.push(child);
+        }
+    }
+    Ok(found)
+}
+
+// Makes an entry out of a decrypted file. By pass's convention, the first line
+// is the password and anything can come after it. A "login:" or "user:" line
+// is taken as the username. The "name: value" lines right after the password
+// are custom fields, like "url: https://example.com", and everything from the
+// first line that isn't one is kept as notes. A blank line ends the fields
+// without going in the notes.
+pub fn parse_file(name: &str, text: &str) -> Entry {
+    let mut lines = text.lines();
+    let password = lines.next().unwrap_or("").to_owned();
+    let mut username = String::new();
+    let mut fields = crate::Fields::new();
+    let mut notes = Vec::new();
+    let mut in_fields = true;
+    for line in lines {
+        let value = ["login:", "user:", "username:"].iter().find_map(|prefix| {
+            match line.get(..prefix.len()) {
+                Some(start) if start.eq_ignore_ascii_case(prefix) => Some(line[prefix.len()..].trim()),
+                _ => None,
+            }
+        });
+        match (value, in_fields.then(|| field_line(line)).flatten()) {
+            (Some(value), _) if username.is_empty() => username = value.to_owned(),
+            (_, Some((field, value))) => fields = fields.update(field.to_owned(), value.to_owned()),
+            _ if in_fields && line.is_empty() => in_fields = false,
+            _ => {
+                in_fields = false;
+                notes.push(line);
+            },
+        }
+    }
+    Entry{
+        name: name.to_owned(),
+        username,
+        password,
+        notes: notes.join("\n").trim_end().to_owned(),
+        fields,
+        ..Entry::default()
+    }
+}
+
+// Splits a "name: value" line. A username line is never a field. There has to
+// be a space after the colon so that a URL on its own, like
+// "https://example.com", isn't one.
+fn field_line(line: &str) -> Option<(&str, &str)> {
+    let (field, value) = line.split_once(':')?;
+    let username = ["login", "user", "username"].iter().any(|prefix| field.eq_ignore_ascii_case(prefix));
+    if username || field.is_empty() || field.trim() != field || !(value.is_empty() || value.starts_with(' ')) {
+        return None;
+    }
+    Some((field, value.trim()))
+}
+
+// The other way around from `parse_file`. Custom fields are written as
+// "name: value" lines, the way pass users tend to write URLs. pass has nowhere
+// to put tags or IDs, so they're left out.
+pub fn format_file(entry: &Entry) -> String {
+    let mut text = format!("{}\n", entry.password);
+    if !entry.username.is_empty() {
+        text.push_str(&format!("login: {}\n", entry.username));
+    }
+    for (name, value) in &entry.fields {
+        text.push_str(&format!("{}: {}\n", name, value));
+    }
+    // Notes that start like a field, or with a blank line, need a blank line
+    // in front of them so that they're read back as notes.
+    let first = entry.notes.lines().next().unwrap_or("");
+    if first.is_empty() && !entry.notes.is_empty() || field_line(first).is_some() {
+        text.push('\n');
+    }
+    if !entry.notes.is_empty() {
+        text.push_str(&entry.notes);
+        text.push('\n');
+    }
+    text
+}
+
+// Writes entries into a password store, replacing any files that are already
+// there. Each file is encrypted for the keys in the nearest .gpg-id, like pass
+// does. Returns how many were written.
+pub fn write(store: &Path, entries: &Entries, crypt: &Crypt) -> Result<usize, String> {
+    for entry in entries.values() {
+        // A name like "../x" or "/x" would end up outside of the store.
+        if crate::check_name(&entry.name).is_err() || entry.name.split('/').any(|part| part == "." || part == "..") {
+            return Err(format!("\"{}\" can't be written to a password store.", entry.name));
+        }
+    }
+    for entry in entries.values() {
+        let path = store.join(format!("{}.gpg", entry.name));
+        let dir = path.parent().unwrap();
+        std::fs::create_dir_all(dir).map_err(|e| format!("Failed creating {}: {}", dir.display(), e))?;
+        let recipients = match crypt {
+            Crypt::Plain => Vec::new(),
+            Crypt::Gpg(_) => recipients(store, dir)?,
+        };
+        crypt.encrypt(&path, &recipients, &format_file(entry))?;
+    }
+    Ok(entries.len())
+}
+
+// Reads the keys out of the nearest .gpg-id, from `dir` up to the top of the
+// store.
+fn recipients(store: &Path, dir: &Path) -> Result<Vec<String>, String> {
+    let gpg_id = dir.ancestors()
+        .take_while(|ancestor| ancestor.starts_with(store))
+        .map(|ancestor| ancestor.join(".gpg-id"))
+        .find(|gpg_id| gpg_id.is_file())
+        .ok_or_else(|| format!("There's no .gpg-id in {}. Run `pass init` there first.", store.display()))?;
+    let text = std::fs::read_to_string(&gpg_id)
+        .map_err(|e| format!("Failed reading {}: {}", gpg_id.display(), e))?;
+    Ok(text.lines().map(str::trim).filter(|line| !line.is_empty()).map(String::from).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static S: fn(&'static str) -> String = String::from;
+
+    #[test]
+    fn test_read() {
+        let store = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/import/pass");
+        let entries = read(&store, &Crypt::Plain).unwrap();
+        let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, vec!["email", "work/aws/prod root", "work/wifi"]);
+
+        assert_eq!(entries[0], Entry{
+            name: S("email"),
+            username: S("me@example.com"),
+            password: S("hunter2"),
+            fields: crate::Fields::new()
+                .update(S("url"), S("https://mail.example.com"))
+                .update(S("Recovery code"), S("abcd-efgh")),
+            ..Entry::default()
+        });
+        assert_eq!(entries[1].username, "admin");
+        assert_eq!(entries[2].username, "");
+        assert_eq!(entries[2].notes, "The password is on the router.");
+
+        assert!(read(&store.join("email.gpg"), &Crypt::Plain).is_err());
+    }
+
+    #[test]
+    fn test_parse_file() {
+        assert_eq!(parse_file("x", ""), Entry{name: S("x"), ..Entry::default()});
+        // Only the first username line counts.
+        let entry = parse_file("x", "secret\nUser: me\nlogin: someone else\n\n");
+        assert_eq!((entry.username.as_str(), entry.password.as_str()), ("me", "secret"));
+        assert_eq!(entry.notes, "login: someone else");
+
+        // Fields are the lines right after the password, up to the notes.
+        let entry = parse_file("x", "secret\nurl: https://example.com\npin: 1234\n\nnotes: not a field\nhttps://example.com\n");
+        assert_eq!(entry.fields, crate::Fields::new().update(S("url"), S("https://example.com")).update(S("pin"), S("1234")));
+        assert_eq!(entry.notes, "notes: not a field\nhttps://example.com");
+        let entry = parse_file("x", "secret\nhttps://example.com\npin: 1234\n");
+        assert_eq!(entry.fields, crate::Fields::new());
+        assert_eq!(entry.notes, "https://example.com\npin: 1234");
+    }
+
+    #[test]
+    fn test_write() {
+        let temp = mktemp::Temp::new_dir().unwrap();
+        let store = temp.to_path_buf();
+        let entries = Entries::new()
+            .update(S("email"), Entry{
+                name: S("email"),
+                username: S("me@example.com"),
+                password: S("hunter2"),
+                notes: S("first line\nsecond line"),
+                id: S("first"),
+                tags: crate::parse_tags("mail"),
+                fields: crate::Fields::new().update(S("url"), S("https://mail.example.com")),
+            })
+            .update(S("work/wifi"), Entry{
+                name: S("work/wifi"),
+                password: S("correct horse"),
+                ..Entry::default()
+            });
+        assert_eq!(write(&store, &entries, &Crypt::Plain), Ok(2));
+        assert_eq!(
+            std::fs::read_to_string(store.join("email.gpg")).unwrap(),
+            "hunter2\nlogin: me@example.com\nurl: https://mail.example.com\nfirst line\nsecond line\n",
+        );
+
+        // Everything but the tags and ID comes back.
+        let read_back = read(&store, &Crypt::Plain).unwrap();
+        let email = entries.get("email").unwrap();
+        assert_eq!(read_back[0], Entry{id: S(""), tags: crate::Tags::new(), ..email.clone()});
+        assert_eq!(read_back[1], Entry{name: S("work/wifi"), password: S("correct horse"), ..Entry::default()});
+
+        // gpg needs to know who to encrypt for.
+        let temp = mktemp::Temp::new_dir().unwrap();
+        let empty = temp.to_path_buf();
+        assert!(write(&empty, &entries, &Crypt::Gpg(S("gpg"))).unwrap_err().contains(".gpg-id"));
+
+        for name in ["../x", "/etc/x", "a//b", "a/", "a/./b"] {
+            let escaping = Entries::new().update(S(name), Entry{name: S(name), ..Entry::default()});
+            assert!(write(&empty, &escaping, &Crypt::Plain).is_err(), "{}", name);
+        }
+        assert_eq!(std::fs::read_dir(&empty).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let temp = mktemp::Temp::new_dir().unwrap();
+        let store = temp.to_path_buf();
+        let entry = Entry{
+            name: S("x"),
+            username: S("me"),
+            password: S("secret"),
+            notes: S("question: not a field\nanswer"),
+            fields: crate::Fields::new().update(S("security question"), S("first pet")),
+            ..Entry::default()
+        };
+        let blank = Entry{name: S("y"), notes: S("\nafter a blank line"), ..Entry::default()};
+        let entries = Entries::new().update(S("x"), entry.clone()).update(S("y"), blank.clone());
+        write(&store, &entries, &Crypt::Plain).unwrap();
+        assert_eq!(read(&store, &Crypt::Plain).unwrap(), vec![entry, blank]);
+    }
+
+    #[test]
+    fn test_recipients() {
+        let temp = mktemp::Temp::new_dir().unwrap();
+        let store = temp.to_path_buf();
+        std::fs::create_dir_all(store.join("work/aws")).unwrap();
+        std::fs::write(store.join(".gpg-id"), "me@example.com\n").unwrap();
+        std::fs::write(store.join("work/.gpg-id"), "work@example.com\nteam@example.com\n").unwrap();
+        assert_eq!(recipients(&store, &store), Ok(vec![S("me@example.com")]));
+        assert_eq!(recipients(&store, &store.join("work/aws")), Ok(vec![S("work@example.com"), S("team@example.com")]));
+    }
+}
@@ -0,0 +1,546 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use pm::EntriesStuff;
+
+use crate::agent;
+
+// `pm serve` answers JSON over HTTP so that other programs, like editor
+// plugins, can get at entries without parsing `list`. It's meant to be local:
+// either a Unix socket that only we can use or a loopback address with a
+// bearer token.
+//
+//     GET    /entries             list, with folder, tag, any_tag, and search
+//                                 in the query to narrow it down
+//     POST   /entries             create
+//     GET    /entries/<entry>     get, with the entry picked like on the
+//                                 command line
+//     PUT    /entries/<entry>     update
+//     DELETE /entries/<entry>     delete
+//
+// Listing leaves out passwords, notes, and anything else that could be
+// secret. Getting one entry is how those are read.
+
+pub enum Listener {
+    Unix(UnixListener),
+    Tcp(TcpListener),
+}
+
+// What's being served.
+pub struct State {
+    pub vault: pm::vault::Vault,
+    pub entries: pm::Entries,
+    pub modified: Option<SystemTime>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: Vec<(String, String)>,
+    pub authorization: String,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub body: serde_json::Value,
+}
+
+impl Response {
+    fn ok(body: serde_json::Value) -> Response {
+        Response{status: 200, body}
+    }
+
+    fn error(status: u16, message: &str) -> Response {
+        Response{status, body: serde_json::json!({"error": message})}
+    }
+}
+
+// What can be given when creating or updating an entry. Anything left out of
+// an update stays the way it was. The ID can't be changed.
+#[derive(serde_derive::Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct Changes {
+    name: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+    notes: Option<String>,
+    tags: Option<pm::Tags>,
+    fields: Option<pm::Fields>,
+}
+
+// The parts of an entry that are safe to list.
+fn summary(index: usize, entry: &pm::Entry) -> serde_json::Value {
+    let fields: pm::Fields = entry.fields.iter()
+        .filter(|(name, _)| !pm::export::is_secret(name))
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect();
+    serde_json::json!({
+        "index": index,
+        "name": entry.name,
+        "id": entry.id,
+        "username": entry.username,
+        "tags": entry.tags,
+        "fields": fields,
+    })
+}
+
+fn check_tags(tags: &pm::Tags) -> Result<(), String> {
+    tags.iter().try_for_each(|tag| pm::check_tag(tag))
+}
+
+// Answers a request. Anything that changes the entries is saved before the
+// response goes out.
+pub fn handle(state: &mut State, request: &Request) -> Response {
+    let selector = match request.path.strip_prefix("/entries") {
+        Some("") | Some("/") => None,
+        Some(rest) if rest.starts_with('/') => Some(rest[1..].to_owned()),
+        _ => return Response::error(404, "There's nothing here. Try /entries."),
+    };
+    let changes = || -> Result<Changes, Response> {
+        serde_json::from_slice(&request.body).map_err(|e| Response::error(400, &format!("That isn't an entry: {}", e)))
+    };
+    match (request.method.as_str(), selector) {
+        ("GET", None) => {
+            let query = |key: &str| -> Vec<String> {
+                request.query.iter().filter(|(k, _)| k == key).map(|(_, v)| v.clone()).collect()
+            };
+            let filter = pm::Filter{
+                folder: query("folder").into_iter().next().unwrap_or_default(),
+                all_tags: query("tag"),
+                any_tags: query("any_tag"),
+                search: query("search").into_iter().next().unwrap_or_default(),
+            };
+            let listed: Vec<serde_json::Value> = state.entries.values()
+                .enumerate()
+                .filter(|(_, entry)| filter.matches(entry))
+                .map(|(i, entry)| summary(i + 1, entry))
+                .collect();
+            Response::ok(serde_json::Value::from(listed))
+        },
+        ("POST", None) => {
+            let changes = match changes() {
+                Err(response) => return response,
+                Ok(changes) => changes,
+            };
+            let name = match changes.name {
+                None => return Response::error(400, "A new entry needs a name."),
+                Some(name) => name,
+            };
+            if let Err(e) = pm::check_name(&name) {
+                return Response::error(400, &e);
+            }
+            if state.entries.contains_key(&name) {
+                return Response::error(409, &format!("An entry with the name \"{}\" already exists.", name));
+            }
+            let tags = changes.tags.unwrap_or_default();
+            if let Err(e) = check_tags(&tags) {
+                return Response::error(400, &e);
+            }
+            let entry = pm::Entry{
+                name: name.clone(),
+                username: changes.username.unwrap_or_default(),
+                password: changes.password.unwrap_or_default(),
+                notes: changes.notes.unwrap_or_default(),
+                id: state.entries.new_id(),
+                tags,
+                fields: changes.fields.unwrap_or_default(),
+            };
+            let entries = state.entries.update(name, entry.clone());
+            match save(state, entries) {
+                Err(response) => response,
+                Ok(()) => Response{status: 201, body: serde_json::to_value(&entry).expect("Failed serializing an entry.")},
+            }
+        },
+        (method, Some(selector)) => {
+            let entry = match state.entries.getish(&selector) {
+                Err(e) => return Response::error(404, &e),
+                Ok(entry) => entry,
+            };
+            match method {
+                "GET" => Response::ok(serde_json::to_value(&entry).expect("Failed serializing an entry.")),
+                "PUT" | "PATCH" => {
+                    let changes = match changes() {
+                        Err(response) => return response,
+                        Ok(changes) => changes,
+                    };
+                    let name = changes.name.unwrap_or_else(|| entry.name.clone());
+                    if let Err(e) = pm::check_name(&name) {
+                        return Response::error(400, &e);
+                    }
+                    if name != entry.name && state.entries.contains_key(&name) {
+                        return Response::error(409, &format!("An entry with the name \"{}\" already exists.", name));
+                    }
+                    if let Some(Err(e)) = changes.tags.as_ref().map(check_tags) {
+                        return Response::error(400, &e);
+                    }
+                    let updated = pm::Entry{
+                        name: name.clone(),
+                        username: changes.username.unwrap_or(entry.username),
+                        password: changes.password.unwrap_or(entry.password),
+                        notes: changes.notes.unwrap_or(entry.notes),
+                        id: entry.id,
+                        tags: changes.tags.unwrap_or(entry.tags),
+                        fields: changes.fields.unwrap_or(entry.fields),
+                    };
+                    let entries = state.entries.without(&entry.name).update(name, updated.clone());
+                    match save(state, entries) {
+                        Err(response) => response,
+                        Ok(()) => Response::ok(serde_json::to_value(&updated).expect("Failed serializing an entry.")),
+                    }
+                },
+                "DELETE" => {
+                    let entries = state.entries.without(&entry.name);
+                    match save(state, entries) {
+                        Err(response) => response,
+                        Ok(()) => Response::ok(serde_json::json!({"deleted": entry.name})),
+                    }
+                },
+                _ => Response::error(405, "An entry can be read with GET, changed with PUT, or deleted with DELETE."),
+            }
+        },
+        (_, None) => Response::error(405, "Entries can be listed with GET or created with POST."),
+    }
+}
+
+// Saves the entries the same way the commands do, agent and all.
+fn save(state: &mut State, entries: pm::Entries) -> Result<(), Response> {
+    state.vault.save(&entries).map_err(|e| Response::error(500, &e))?;
+    agent::update(&agent::socket_path(), &state.vault, &entries);
+    state.entries = entries;
+    state.modified = agent::modified(&state.vault.path);
+    Ok(())
+}
+
+// Undoes percent-encoding. In a query, a + is a space too.
+fn decode(text: &str, query: bool) -> String {
+    let hex = |byte: u8| (byte as char).to_digit(16).map(|digit| digit as u8);
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() && hex(bytes[i + 1]).is_some() && hex(bytes[i + 2]).is_some() => {
+                decoded.push(hex(bytes[i + 1]).unwrap() * 16 + hex(bytes[i + 2]).unwrap());
+                i += 3;
+            },
+            b'+' if query => {
+                decoded.push(b' ');
+                i += 1;
+            },
+            byte => {
+                decoded.push(byte);
+                i += 1;
+            },
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+// The most that's read of a request, so that nobody can make us hold on to
+// an endless one.
+const MAX_REQUEST: u64 = 1024 * 1024;
+
+// Reads an HTTP request. Only bodies with a Content-Length are understood,
+// which is all that any client sends for something this size.
+pub fn read_request(stream: impl Read) -> Result<Request, String> {
+    let mut reader = BufReader::new(stream.take(MAX_REQUEST));
+    let mut line = String::new();
+    reader.read_line(&mut line).map_err(|e| e.to_string())?;
+    let mut parts = line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method.to_owned(), target.to_owned()),
+        _ => return Err(String::from("That isn't an HTTP request.")),
+    };
+    let mut request = Request{method, ..Request::default()};
+    let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+    request.path = decode(path, false);
+    request.query = query.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode(key, true), decode(value, true))
+        })
+        .collect();
+
+    let mut length: u64 = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).map_err(|e| e.to_string())? == 0 {
+            return Err(String::from("The request ended in the middle of its headers."));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once(':').unwrap_or((line, ""));
+        match name.trim().to_lowercase().as_str() {
+            "content-length" => length = value.trim().parse().map_err(|_| String::from("The Content-Length isn't a number."))?,
+            "authorization" => request.authorization = value.trim().to_owned(),
+            "transfer-encoding" => return Err(String::from("Send a Content-Length instead of a Transfer-Encoding.")),
+            _ => {},
+        }
+    }
+    // Checked before anything is made that big. The rest of the request
+    // can't be any longer than this anyway.
+    if length > MAX_REQUEST {
+        return Err(String::from("The body is too big."));
+    }
+    request.body = vec![0; length as usize];
+    reader.read_exact(&mut request.body).map_err(|_| String::from("The body is shorter than its Content-Length, or too big."))?;
+    Ok(request)
+}
+
+pub fn write_response(mut stream: impl Write, response: &Response) -> std::io::Result<()> {
+    let reason = match response.status {
+        200 => "OK",
+        201 => "Created",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        _ => "Internal Server Error",
+    };
+    let body = format!("{}\n", response.body);
+    write!(stream, "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n", response.status, reason, body.len())?;
+    if response.status == 401 {
+        write!(stream, "WWW-Authenticate: Bearer\r\n")?;
+    }
+    write!(stream, "\r\n{}", body)?;
+    stream.flush()
+}
+
+// Compares the whole token every time, so that how long it takes doesn't say
+// how much of it was right.
+fn same_token(given: &str, token: &str) -> bool {
+    given.len() == token.len() && given.bytes().zip(token.bytes()).fold(0, |different, (a, b)| different | (a ^ b)) == 0
+}
+
+// Makes a token for --listen when one isn't given.
+pub fn generate_token() -> String {
+    use ssh_key::rand_core::RngCore;
+    let mut bytes = [0; 24];
+    ssh_key::rand_core::OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Makes the socket, readable and writable only by us. Like the agent's, a
+// socket nobody is listening on anymore is replaced, but nothing else is.
+pub fn listen_unix(socket: &Path) -> Result<UnixListener, String> {
+    if let Ok(metadata) = std::fs::symlink_metadata(socket) {
+        if !metadata.file_type().is_socket() {
+            return Err(format!("{} is already there and isn't a socket.", socket.display()));
+        }
+        if UnixStream::connect(socket).is_ok() {
+            return Err(format!("Something is already listening on {}.", socket.display()));
+        }
+        let _ = std::fs::remove_file(socket);
+    }
+    let old_umask = unsafe { libc::umask(0o177) };
+    let listener = UnixListener::bind(socket);
+    unsafe { libc::umask(old_umask) };
+    listener.map_err(|e| format!("Failed listening on {}: {}", socket.display(), e))
+}
+
+// Answers one connection. Over a socket, only we can connect; over TCP, the
+// token has to be there. Either way, if it's been given, it's checked.
+fn connection<S>(stream: S, state: &mut State, token: Option<&str>, reopen: &mut dyn FnMut() -> Result<(pm::vault::Vault, pm::Entries), String>)
+where for<'a> &'a S: Read + Write {
+    let request = match read_request(&stream) {
+        Err(e) => {
+            let _ = write_response(&stream, &Response::error(400, &e));
+            return;
+        },
+        Ok(request) => request,
+    };
+    let response = if token.is_some_and(|token| !same_token(&request.authorization, &format!("Bearer {}", token))) {
+        Response::error(401, "The bearer token is missing or wrong.")
+    } else if agent::modified(&state.vault.path) != state.modified {
+        // Something else changed the file, so it's read again before
+        // anything is answered from it.
+        match reopen() {
+            Err(e) => Response::error(500, &format!("The file changed and I couldn't read it again: {}", e)),
+            Ok((vault, entries)) => {
+                *state = State{modified: agent::modified(&vault.path), vault, entries};
+                handle(state, &request)
+            },
+        }
+    } else {
+        handle(state, &request)
+    };
+    let _ = write_response(&stream, &response);
+}
+
+// Answers requests, one at a time, until it's killed.
+pub fn serve(listener: Listener, mut state: State, token: Option<String>, reopen: &mut dyn FnMut() -> Result<(pm::vault::Vault, pm::Entries), String>) {
+    loop {
+        match &listener {
+            Listener::Unix(listener) => {
+                if let Ok((stream, _)) = listener.accept() {
+                    if agent::same_user(&stream) {
+                        let _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
+                        connection(stream, &mut state, token.as_deref(), reopen);
+                    }
+                }
+            },
+            Listener::Tcp(listener) => {
+                if let Ok((stream, _)) = listener.accept() {
+                    let _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
+                    connection(stream, &mut state, token.as_deref(), reopen);
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static S: fn(&'static str) -> String = String::from;
+
+    fn request(method: &str, path: &str, body: &str) -> Request {
+        Request{method: method.to_owned(), path: path.to_owned(), body: body.as_bytes().to_vec(), ..Request::default()}
+    }
+
+    fn state(temp: &mktemp::Temp) -> State {
+        let path = temp.join("passwords.json");
+        let entries = pm::Entries::new().update(S("work/email"), pm::Entry{
+            name: S("work/email"),
+            username: S("me@example.com"),
+            password: S("hunter2"),
+            notes: S("notes"),
+            id: S("1a2b3c4d"),
+            tags: pm::parse_tags("work"),
+            fields: pm::Fields::new().update(S("url"), S("https://mail.example.com")).update(S("pin"), S("1234")),
+        });
+        entries.save(&path).unwrap();
+        State{vault: pm::vault::Vault::plain(&path), modified: agent::modified(&path), entries}
+    }
+
+    #[test]
+    fn test_list_and_get() {
+        let temp = mktemp::Temp::new_dir().unwrap();
+        let mut state = state(&temp);
+
+        let listed = handle(&mut state, &request("GET", "/entries", ""));
+        assert_eq!(listed, Response::ok(serde_json::json!([{
+            "index": 1,
+            "name": "work/email",
+            "id": "1a2b3c4d",
+            "username": "me@example.com",
+            "tags": ["work"],
+            "fields": {"url": "https://mail.example.com"},
+        }])));
+        let searched = Request{query: vec![(S("search"), S("nobody"))], ..request("GET", "/entries", "")};
+        assert_eq!(handle(&mut state, &searched), Response::ok(serde_json::json!([])));
+        let tagged = Request{query: vec![(S("tag"), S("work")), (S("folder"), S("work"))], ..request("GET", "/entries/", "")};
+        assert_eq!(handle(&mut state, &tagged).body.as_array().map(Vec::len), Some(1));
+
+        let got = handle(&mut state, &request("GET", "/entries/id:1a2b3c4d", ""));
+        assert_eq!(got.body["password"], "hunter2");
+        assert_eq!(got.body["fields"]["pin"], "1234");
+        assert_eq!(handle(&mut state, &request("GET", "/entries/work/email", "")).status, 200);
+        assert_eq!(handle(&mut state, &request("GET", "/entries/nope", "")).status, 404);
+        assert_eq!(handle(&mut state, &request("GET", "/elsewhere", "")).status, 404);
+        assert_eq!(handle(&mut state, &request("PUT", "/entries", "")).status, 405);
+    }
+
+    #[test]
+    fn test_create_update_and_delete() {
+        let temp = mktemp::Temp::new_dir().unwrap();
+        let mut state = state(&temp);
+        let saved = |state: &State| pm::Entries::load(&state.vault.path);
+
+        let created = handle(&mut state, &request("POST", "/entries", r#"{"name": "github", "password": "token", "tags": ["dev"]}"#));
+        assert_eq!(created.status, 201);
+        assert_eq!(created.body["id"].as_str().map(str::len), Some(8));
+        assert_eq!(saved(&state).get("github").map(|entry| entry.password.as_str()), Some("token"));
+        assert_eq!(handle(&mut state, &request("POST", "/entries", r#"{"name": "github"}"#)).status, 409);
+        assert_eq!(handle(&mut state, &request("POST", "/entries", r#"{"password": "x"}"#)).status, 400);
+        assert_eq!(handle(&mut state, &request("POST", "/entries", r#"{"name": "x", "id": "mine"}"#)).status, 400);
+        assert_eq!(handle(&mut state, &request("POST", "/entries", r#"{"name": "x", "tags": ["two words"]}"#)).status, 400);
+
+        let updated = handle(&mut state, &request("PUT", "/entries/github", r#"{"name": "dev/github", "username": "me"}"#));
+        assert_eq!(updated.status, 200);
+        let entry = saved(&state).get("dev/github").cloned().unwrap();
+        assert_eq!((entry.username.as_str(), entry.password.as_str()), ("me", "token"));
+        assert_eq!(entry.id, created.body["id"]);
+        assert!(!saved(&state).contains_key("github"));
+        assert_eq!(handle(&mut state, &request("PUT", "/entries/dev/github", r#"{"name": "work/email"}"#)).status, 409);
+        assert_eq!(handle(&mut state, &request("PUT", "/entries/dev/github", "nope")).status, 400);
+
+        assert_eq!(handle(&mut state, &request("DELETE", "/entries/dev/github", "")), Response::ok(serde_json::json!({"deleted": "dev/github"})));
+        assert_eq!(saved(&state).keys().collect::<Vec<_>>(), vec!["work/email"]);
+    }
+
+    #[test]
+    fn test_read_request() {
+        let text = "POST /entries/work%2Femail?search=a+b&tag=x%20y&flag HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer abc\r\nContent-Length: 4\r\n\r\n{}\r\nextra";
+        assert_eq!(read_request(text.as_bytes()), Ok(Request{
+            method: S("POST"),
+            path: S("/entries/work/email"),
+            query: vec![(S("search"), S("a b")), (S("tag"), S("x y")), (S("flag"), S(""))],
+            authorization: S("Bearer abc"),
+            body: b"{}\r\n".to_vec(),
+        }));
+        assert!(read_request("nonsense".as_bytes()).is_err());
+        assert!(read_request("GET / HTTP/1.1\r\nContent-Length: 10\r\n\r\nshort".as_bytes()).is_err());
+        assert_eq!(read_request("POST / HTTP/1.1\r\nContent-Length: 18446744073709551615\r\n\r\n".as_bytes()), Err(S("The body is too big.")));
+        assert!(read_request(format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n", MAX_REQUEST + 1).as_bytes()).is_err());
+        assert!(read_request("GET / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n".as_bytes()).is_err());
+        assert_eq!(decode("100%", false), "100%");
+        assert_eq!(decode("%zz%é", false), "%zz%é");
+    }
+
+    #[test]
+    fn test_serve() {
+        let temp = mktemp::Temp::new_dir().unwrap();
+        let state = state(&temp);
+        let path = state.vault.path.clone();
+        let socket = temp.join("pm.sock");
+        let listener = listen_unix(&socket).unwrap();
+        assert!(listen_unix(&socket).is_err());
+        std::thread::spawn(move || {
+            let mut reopen = || pm::vault::Vault::open(&path, &[], &mut || Err(S("no passphrase")));
+            serve(Listener::Unix(listener), state, Some(S("secret")), &mut reopen)
+        });
+
+        let ask = |request: &str| {
+            let mut stream = UnixStream::connect(&socket).unwrap();
+            stream.write_all(request.as_bytes()).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        assert!(ask("GET /entries HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+        let response = ask("GET /entries/work%2Femail HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("\"password\":\"hunter2\""));
+
+        // Changes made by something else are picked up.
+        let changed = pm::Entries::new().update(S("other"), pm::Entry{name: S("other"), id: S("99999999"), ..pm::Entry::default()});
+        std::thread::sleep(Duration::from_millis(20));
+        changed.save(&temp.join("passwords.json")).unwrap();
+        assert!(ask("GET /entries HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n").contains("\"name\":\"other\""));
+
+        // A stale socket is replaced, but not a file.
+        std::fs::write(temp.join("file"), "").unwrap();
+        assert!(listen_unix(&temp.join("file")).is_err());
+    }
+
+    #[test]
+    fn test_same_token() {
+        assert!(same_token("Bearer abc", "Bearer abc"));
+        assert!(!same_token("Bearer abd", "Bearer abc"));
+        assert!(!same_token("", "Bearer abc"));
+        assert_eq!(generate_token().len(), 48);
+    }
+}
@@ -0,0 +1,137 @@
+use std::io::{Read, Write};
+
+use age::secrecy::ExposeSecret;
+use age::x25519::{Identity, Recipient};
+
+use crate::Entry;
+
+// Bundles of entries encrypted for someone else, so that they can be handed
+// over as a file. A bundle is an age file with a JSON list of entries in it,
+// so `age --decrypt` can open one too. Keys are age's X25519 keys, in the
+// same files age-keygen writes.
+
+// Makes a new key. Returns what goes in the key file and the public key to
+// give to whoever is sharing with you.
+pub fn generate() -> (String, String) {
+    let identity = Identity::generate();
+    let public = identity.to_public().to_string();
+    let file = format!("# public key: {}\n{}\n", public, identity.to_string().expose_secret());
+    (file, public)
+}
+
+// Reads public keys, one per line. Blank lines and comments are skipped, like
+// in age's recipients files.
+pub fn parse_recipients(text: &str) -> Result<Vec<Recipient>, String> {
+    key_lines(text)
+        .map(|line| line.parse().map_err(|_| format!("\"{}\" isn't an age public key.", line)))
+        .collect()
+}
+
+// Reads private keys out of a key file.
+pub fn parse_identities(text: &str) -> Result<Vec<Identity>, String> {
+    let identities = key_lines(text)
+        .map(|line| line.parse().map_err(|_| String::from("The key file has something in it that isn't an age private key.")))
+        .collect::<Result<Vec<Identity>, String>>()?;
+    if identities.is_empty() {
+        return Err(String::from("There aren't any keys in the key file."));
+    }
+    Ok(identities)
+}
+
+fn key_lines(text: &str) -> impl Iterator<Item = &str> {
+    text.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#'))
+}
+
+// Encrypts entries so that any one of the recipients can open them.
+pub fn seal(entries: &[Entry], recipients: &[Recipient]) -> Result<Vec<u8>, String> {
+    let encryptor = age::Encryptor::with_recipients(recipients.iter().map(|recipient| recipient as &dyn age::Recipient))
+        .map_err(|e| format!("Failed encrypting the bundle: {}", e))?;
+    let json = serde_json::to_vec(entries).expect("Failed serializing entries.");
+    let mut bundle = Vec::new();
+    let mut writer = encryptor.wrap_output(&mut bundle).map_err(|e| format!("Failed encrypting the bundle: {}", e))?;
+    writer.write_all(&json).map_err(|e| format!("Failed encrypting the bundle: {}", e))?;
+    writer.finish().map_err(|e| format!("Failed encrypting the bundle: {}", e))?;
+    Ok(bundle)
+}
+
+// Decrypts a bundle with whichever of the keys it was made for.
+pub fn open(bundle: &[u8], identities: &[Identity]) -> Result<Vec<Entry>, String> {
+    let decryptor = age::Decryptor::new(bundle).map_err(|e| format!("That isn't a bundle: {}", e))?;
+    let mut reader = decryptor.decrypt(identities.iter().map(|identity| identity as &dyn age::Identity))
+        .map_err(|e| format!("Failed decrypting the bundle: {}", e))?;
+    let mut json = Vec::new();
+    reader.read_to_end(&mut json).map_err(|e| format!("Failed decrypting the bundle: {}", e))?;
+    serde_json::from_slice(&json).map_err(|e| format!("The bundle doesn't have entries in it: {}", e))
+}
+
+// Leaves out received entries with names that couldn't have been made here,
+// like "a//b" or "/a". A bundle can come from anyone, and names like that
+// break the folders in `list` and can end up as paths.
+pub fn checked(entries: Vec<Entry>) -> crate::import::Imported {
+    let (entries, bad): (Vec<Entry>, Vec<Entry>) = entries.into_iter().partition(|entry| crate::check_name(&entry.name).is_ok());
+    crate::import::Imported{
+        entries,
+        skipped: bad.iter().map(|entry| format!("\"{}\", which isn't a valid name", entry.name)).collect(),
+        ..crate::import::Imported::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static S: fn(&'static str) -> String = String::from;
+
+    #[test]
+    fn test_seal_and_open() {
+        let (file, public) = generate();
+        assert!(public.starts_with("age1"));
+        assert!(file.starts_with(&format!("# public key: {}\nAGE-SECRET-KEY-", public)));
+        let identities = parse_identities(&file).unwrap();
+        let recipients = parse_recipients(&format!("# Alice\n{}\n\n", public)).unwrap();
+
+        let entries = vec![Entry{
+            name: S("work/email"),
+            username: S("me@example.com"),
+            password: S("hunter2"),
+            notes: S("notes"),
+            id: S("1a2b3c4d"),
+            tags: crate::parse_tags("work"),
+            fields: crate::Fields::new().update(S("url"), S("https://mail.example.com")),
+        }];
+        let bundle = seal(&entries, &recipients).unwrap();
+        assert!(bundle.starts_with(b"age-encryption.org/v1\n"));
+        assert_eq!(open(&bundle, &identities), Ok(entries.clone()));
+
+        // Someone else's key can't open it.
+        let (other, _) = generate();
+        assert!(open(&bundle, &parse_identities(&other).unwrap()).is_err());
+
+        // But it can be made for more than one person.
+        let (_, other_public) = generate();
+        let both = parse_recipients(&format!("{}\n{}\n", other_public, public)).unwrap();
+        assert_eq!(open(&seal(&entries, &both).unwrap(), &identities), Ok(entries));
+    }
+
+    #[test]
+    fn test_parse_keys() {
+        assert!(parse_recipients("age1nope").is_err());
+        assert_eq!(parse_recipients("# nobody\n").map(|recipients| recipients.len()), Ok(0));
+        assert!(parse_identities("# nothing here\n").is_err());
+        assert!(parse_identities("AGE-SECRET-KEY-1NOPE").is_err());
+        assert!(open(b"not a bundle", &[]).is_err());
+    }
+
+    #[test]
+    fn test_checked() {
+        let entry = |name: &str| Entry{name: name.to_owned(), ..Entry::default()};
+        let received = checked(vec![entry("work/email"), entry("a//b"), entry("/etc/x"), entry("a/"), entry("")]);
+        assert_eq!(received.entries, vec![entry("work/email")]);
+        assert_eq!(received.skipped, vec![
+            S("\"a//b\", which isn't a valid name"),
+            S("\"/etc/x\", which isn't a valid name"),
+            S("\"a/\", which isn't a valid name"),
+            S("\"\", which isn't a valid name"),
+        ]);
+    }
+}
@@ -0,0 +1,140 @@
+use rustyline::completion::Completer;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+
+// Splits a shell line into words the way sh would for simple cases: spaces
+// separate words, single quotes keep everything as it is, and in double quotes
+// or outside of quotes a backslash escapes the next character. That's enough
+// to give `show "work/aws prod"` an entry with a space in its name.
+pub fn split_words(line: &str) -> Result<Vec<String>, String> {
+    let (words, closed) = words(line);
+    if closed {
+        Ok(words.into_iter().map(|(_, word)| word).collect())
+    } else {
+        Err(String::from("A quote isn't closed."))
+    }
+}
+
+// The words with where each one starts, and whether the quotes were all
+// closed. Completion needs the unfinished word too, so this doesn't fail.
+fn words(line: &str) -> (Vec<(usize, String)>, bool) {
+    let mut words = Vec::new();
+    let mut current: Option<(usize, String)> = None;
+    let mut quote = None;
+    let mut chars = line.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if quote.is_none() && c.is_whitespace() {
+            words.extend(current.take());
+            continue;
+        }
+        let word = &mut current.get_or_insert_with(|| (i, String::new())).1;
+        match (quote, c) {
+            (Some('\''), '\'') | (Some('"'), '"') => quote = None,
+            (Some('\''), c) => word.push(c),
+            (_, '\\') => word.extend(chars.next().map(|(_, escaped)| escaped)),
+            (None, '\'') | (None, '"') => quote = Some(c),
+            (_, c) => word.push(c),
+        }
+    }
+    words.extend(current);
+    (words, quote.is_none())
+}
+
+// Quotes a word if it needs it, so that completing it gives something that
+// splits back into the same word.
+pub fn quote(word: &str) -> String {
+    if !word.is_empty() && !word.contains(|c: char| c.is_whitespace() || c == '\'' || c == '"' || c == '\\') {
+        String::from(word)
+    } else {
+        format!("\"{}\"", word.replace('\\', r"\\").replace('"', "\\\""))
+    }
+}
+
+// Completes command names for the first word and entry names for the rest.
+// The names are set before each line is read, so they're always the names in
+// the shell's entries.
+pub struct Completions {
+    pub commands: Vec<String>,
+    pub names: Vec<String>,
+}
+
+impl Completions {
+    pub fn complete(&self, line: &str) -> (usize, Vec<String>) {
+        let (mut words, closed) = words(line);
+        // The last word is still being typed unless there's a space after it.
+        let typing = !closed || !line.ends_with(char::is_whitespace);
+        let (start, partial) = match words.pop() {
+            Some(word) if typing => word,
+            Some(word) => {
+                words.push(word);
+                (line.len(), String::new())
+            },
+            None => (line.len(), String::new()),
+        };
+        let candidates = if words.is_empty() { &self.commands } else { &self.names };
+        let completed = candidates.iter()
+            .filter(|candidate| candidate.starts_with(&partial))
+            .map(|candidate| quote(candidate))
+            .collect();
+        (start, completed)
+    }
+}
+
+impl Completer for Completions {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(Completions::complete(self, &line[..pos]))
+    }
+}
+
+impl Hinter for Completions {
+    type Hint = String;
+}
+
+impl Highlighter for Completions {}
+
+impl Validator for Completions {}
+
+impl Helper for Completions {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static S: fn(&'static str) -> String = String::from;
+
+    #[test]
+    fn test_split_words() {
+        assert_eq!(split_words("  show  github "), Ok(vec![S("show"), S("github")]));
+        assert_eq!(split_words(r#"show "work/aws prod" 'it''s' a\ b "\"x\"""#), Ok(vec![S("show"), S("work/aws prod"), S("its"), S("a b"), S("\"x\"")]));
+        assert_eq!(split_words("show ''"), Ok(vec![S("show"), S("")]));
+        assert_eq!(split_words(""), Ok(vec![]));
+        assert!(split_words("show \"work").is_err());
+    }
+
+    #[test]
+    fn test_quote() {
+        for word in ["github", "work/aws prod", "it's", "a\\b\"c", ""] {
+            assert_eq!(split_words(&quote(word)), Ok(vec![S(word)]));
+        }
+        assert_eq!(quote("github"), "github");
+        assert_eq!(quote("work/aws prod"), "\"work/aws prod\"");
+    }
+
+    #[test]
+    fn test_complete() {
+        let completions = Completions{
+            commands: vec![S("list"), S("show"), S("save")],
+            names: vec![S("github"), S("gitlab"), S("work/aws prod")],
+        };
+        assert_eq!(completions.complete(""), (0, vec![S("list"), S("show"), S("save")]));
+        assert_eq!(completions.complete("s"), (0, vec![S("show"), S("save")]));
+        assert_eq!(completions.complete("show git"), (5, vec![S("github"), S("gitlab")]));
+        assert_eq!(completions.complete("show "), (5, vec![S("github"), S("gitlab"), S("\"work/aws prod\"")]));
+        assert_eq!(completions.complete("show \"work/aws "), (5, vec![S("\"work/aws prod\"")]));
+        assert_eq!(completions.complete("show github "), (12, vec![S("github"), S("gitlab"), S("\"work/aws prod\"")]));
+    }
+}
@@ -0,0 +1,128 @@
+use crate::{Entries, EntriesStuff};
+
+// Fills in a template with values from entries, so that a config file can be
+// kept without its secrets in it. A reference looks like
+//
+//     {{ pm "db-prod" "password" }}
+//
+// The first string is the entry, picked the same way as anywhere else, and
+// the second is the field, which is the password if it's left out. Anything
+// else in braces, like another tool's templating, is left alone.
+//
+// It fails closed: if anything can't be found, there's an error instead of a
+// config with a hole in it.
+pub fn render(template: &str, entries: &Entries) -> Result<String, String> {
+    let mut rendered = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let line = template.len() - rest.len() + start;
+        let line = template[..line].matches('\n').count() + 1;
+        let inside = &rest[start + 2..];
+        let end = inside.find("}}");
+        let reference = end.and_then(|end| reference(&inside[..end]));
+        match (end, reference) {
+            (Some(end), Some(reference)) => {
+                let (selector, field) = reference.map_err(|e| format!("Line {}: {}", line, e))?;
+                let entry = entries.getish(&selector).map_err(|e| format!("Line {}: {}", line, e))?;
+                let value = entry.field(&field)
+                    .ok_or_else(|| format!("Line {}: \"{}\" doesn't have a field called \"{}\".", line, entry.name, field))?;
+                rendered.push_str(value);
+                rest = &inside[end + 2..];
+            },
+            (None, _) if inside.trim_start().starts_with("pm ") => {
+                return Err(format!("Line {}: There's no }}}} at the end of this.", line));
+            },
+            _ => {
+                rendered.push_str("{{");
+                rest = inside;
+            },
+        }
+    }
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+// Reads what's between the braces. It's None if it isn't ours, and an error if
+// it's ours but doesn't make sense.
+fn reference(inside: &str) -> Option<Result<(String, String), String>> {
+    let inside = inside.trim();
+    let arguments = inside.strip_prefix("pm")?;
+    if !arguments.starts_with(char::is_whitespace) {
+        return None;
+    }
+    Some(strings(arguments).and_then(|strings| match strings.as_slice() {
+        [entry] => Ok((entry.clone(), String::from("password"))),
+        [entry, field] => Ok((entry.clone(), field.clone())),
+        _ => Err(format!("\"{}\" should look like pm \"entry\" \"field\".", inside)),
+    }))
+}
+
+// Splits out double-quoted strings. A backslash escapes a quote or another
+// backslash.
+fn strings(text: &str) -> Result<Vec<String>, String> {
+    let mut strings = Vec::new();
+    let mut chars = text.trim().chars();
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+        if c != '"' {
+            return Err(format!("Expected a quoted string in \"{}\".", text.trim()));
+        }
+        let mut string = String::new();
+        loop {
+            match chars.next() {
+                None => return Err(format!("A string isn't closed in \"{}\".", text.trim())),
+                Some('"') => break,
+                Some('\\') => match chars.next() {
+                    Some(escaped) => string.push(escaped),
+                    None => return Err(format!("A string isn't closed in \"{}\".", text.trim())),
+                },
+                Some(c) => string.push(c),
+            }
+        }
+        strings.push(string);
+    }
+    Ok(strings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Entry;
+
+    static S: fn(&'static str) -> String = String::from;
+
+    #[test]
+    fn test_render() {
+        let entries = Entries::new().update(S("db-prod"), Entry{
+            name: S("db-prod"),
+            username: S("app"),
+            password: S("hunter2"),
+            id: S("1a2b3c4d"),
+            fields: crate::Fields::new().update(S("host"), S("db.example.com")),
+            ..Entry::default()
+        });
+        let template = "database:\n  host: {{ pm \"db-prod\" \"host\" }}\n  user: {{pm \"id:1a2b3c4d\" \"username\"}}\n  password: \"{{ pm \"db-prod\" }}\"\n";
+        assert_eq!(
+            render(template, &entries),
+            Ok(S("database:\n  host: db.example.com\n  user: app\n  password: \"hunter2\"\n")),
+        );
+
+        // Other templating is left alone.
+        assert_eq!(render("{{ .Values.x }} {{pmx}} {{", &entries), Ok(S("{{ .Values.x }} {{pmx}} {{")));
+        assert_eq!(render("{{ pm \"db\\\"prod\" }}", &Entries::new().update(S("db\"prod"), Entry{name: S("db\"prod"), password: S("x"), ..Entry::default()})), Ok(S("x")));
+    }
+
+    #[test]
+    fn test_render_errors() {
+        let entries = Entries::new().update(S("db-prod"), Entry{name: S("db-prod"), ..Entry::default()});
+        assert_eq!(render("a\nb: {{ pm \"nope\" \"password\" }}", &entries), Err(S("Line 2: No entry with the ID or name \"nope\".")));
+        assert_eq!(render("{{ pm \"db-prod\" \"nope\" }}", &entries), Err(S("Line 1: \"db-prod\" doesn't have a field called \"nope\".")));
+        assert!(render("{{ pm db-prod }}", &entries).is_err());
+        assert!(render("{{ pm \"db-prod\" \"host\" \"extra\" }}", &entries).is_err());
+        assert!(render("{{ pm \"db-prod }}", &entries).is_err());
+        assert!(render("{{ pm \"db-prod\"", &entries).is_err());
+    }
+}
@@ -0,0 +1,259 @@
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, List, ListState, Paragraph, Wrap};
+use ratatui::Frame;
+
+// What a key asks the caller to do. Anything that changes the entries goes
+// back to the usual prompts, so it works the same as `pm add` and the like.
+#[derive(Debug, PartialEq)]
+pub enum Action {
+    Nothing,
+    Quit,
+    Copy(String, &'static str),
+    Add,
+    Edit(String),
+    Delete(String),
+}
+
+// What's on the screen besides the entries.
+#[derive(Debug, Default)]
+pub struct App {
+    // What's been typed to narrow down the list.
+    pub filter: String,
+    pub filtering: bool,
+    // The position in the narrowed down list.
+    pub selected: usize,
+    // Whether secrets are shown in the details.
+    pub reveal: bool,
+    // Says what happened, like what got copied.
+    pub message: String,
+}
+
+const HELP: &str = "/ filter  enter copy password  u copy username  r reveal  a add  e edit  d delete  q quit";
+
+impl App {
+    // The names of the entries that match the filter, the same way `list
+    // --search` does.
+    pub fn names(&self, entries: &pm::Entries) -> Vec<String> {
+        let filter = pm::Filter{search: self.filter.clone(), ..pm::Filter::default()};
+        entries.values().filter(|entry| filter.matches(entry)).map(|entry| entry.name.clone()).collect()
+    }
+
+    pub fn current(&self, entries: &pm::Entries) -> Option<String> {
+        self.names(entries).into_iter().nth(self.selected)
+    }
+
+    pub fn key(&mut self, key: KeyEvent, entries: &pm::Entries) -> Action {
+        self.message.clear();
+        let count = self.names(entries).len();
+        let current = self.current(entries);
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return Action::Quit;
+        }
+        match key.code {
+            KeyCode::Up => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down => self.selected += 1,
+            KeyCode::PageUp => self.selected = self.selected.saturating_sub(10),
+            KeyCode::PageDown => self.selected += 10,
+            KeyCode::Enter if self.filtering => self.filtering = false,
+            KeyCode::Esc if self.filtering => {
+                self.filtering = false;
+                self.filter.clear();
+            },
+            KeyCode::Backspace if self.filtering => {
+                self.filter.pop();
+                self.selected = 0;
+            },
+            KeyCode::Char(c) if self.filtering => {
+                self.filter.push(c);
+                self.selected = 0;
+            },
+            KeyCode::Char('k') => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Char('j') => self.selected += 1,
+            KeyCode::Char('/') => self.filtering = true,
+            KeyCode::Esc => {
+                self.filter.clear();
+                self.selected = 0;
+            },
+            KeyCode::Char('r') => self.reveal = !self.reveal,
+            KeyCode::Char('a') => return Action::Add,
+            KeyCode::Char('q') => return Action::Quit,
+            KeyCode::Enter | KeyCode::Char('p') | KeyCode::Char('u') | KeyCode::Char('e') | KeyCode::Char('d') => {
+                return match (key.code, current) {
+                    (_, None) => Action::Nothing,
+                    (KeyCode::Char('u'), Some(name)) => Action::Copy(name, "username"),
+                    (KeyCode::Char('e'), Some(name)) => Action::Edit(name),
+                    (KeyCode::Char('d'), Some(name)) => Action::Delete(name),
+                    (_, Some(name)) => Action::Copy(name, "password"),
+                };
+            },
+            _ => {},
+        }
+        self.selected = self.selected.min(count.saturating_sub(1));
+        Action::Nothing
+    }
+}
+
+// The lines in the details pane. Secrets are covered up unless they're
+// revealed, with the same number of stars every time so that their length
+// doesn't show either.
+pub fn details(entry: &pm::Entry, reveal: bool) -> Vec<(String, String)> {
+    let mask = |field: &str, value: &str| if reveal || value.is_empty() || !pm::export::is_secret(field) {
+        value.to_owned()
+    } else {
+        String::from("********")
+    };
+    let mut details = vec![
+        (String::from("Name"), entry.name.clone()),
+        (String::from("Username"), entry.username.clone()),
+        (String::from("Password"), mask("password", &entry.password)),
+        (String::from("Notes"), mask("notes", &entry.notes)),
+    ];
+    for (field, value) in &entry.fields {
+        details.push((field.clone(), mask(field, value)));
+    }
+    details.push((String::from("Tags"), entry.tags.iter().cloned().collect::<Vec<_>>().join(" ")));
+    details.push((String::from("ID"), entry.id.clone()));
+    details
+}
+
+pub fn draw(frame: &mut Frame, app: &App, entries: &pm::Entries) {
+    let [main, status] = Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+    let [left, right] = Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)]).areas(main);
+
+    let title = if app.filtering || !app.filter.is_empty() { format!(" Entries /{} ", app.filter) } else { String::from(" Entries ") };
+    let list = List::new(app.names(entries))
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    let mut state = ListState::default().with_selected(Some(app.selected));
+    frame.render_stateful_widget(list, left, &mut state);
+
+    let lines: Vec<Line> = app.current(entries)
+        .and_then(|name| entries.get(&name))
+        .map(|entry| details(entry, app.reveal).into_iter().map(|(field, value)| Line::from(format!("{}: {}", field, value))).collect())
+        .unwrap_or_default();
+    let details = Paragraph::new(lines)
+        .block(Block::default().borders(Borders::ALL).title(" Details "))
+        .wrap(Wrap{trim: false});
+    frame.render_widget(details, right);
+
+    let status_line = if app.filtering {
+        String::from("Type to filter. enter keeps it, esc clears it.")
+    } else if !app.message.is_empty() {
+        app.message.clone()
+    } else {
+        String::from(HELP)
+    };
+    frame.render_widget(Paragraph::new(status_line), status);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static S: fn(&'static str) -> String = String::from;
+
+    fn press(app: &mut App, keys: &str, entries: &pm::Entries) -> Action {
+        keys.chars().map(|c| app.key(KeyEvent::from(KeyCode::Char(c)), entries)).last().unwrap_or(Action::Nothing)
+    }
+
+    #[test]
+    fn test_moving() {
+        let entries = pm::Entries::new()
+            .update(S("github"), pm::Entry{name: S("github"), ..pm::Entry::default()})
+            .update(S("gitlab"), pm::Entry{name: S("gitlab"), ..pm::Entry::default()})
+            .update(S("work/aws"), pm::Entry{name: S("work/aws"), ..pm::Entry::default()});
+        let mut app = App::default();
+        assert_eq!(app.current(&entries), Some(S("github")));
+        press(&mut app, "jjjj", &entries);
+        assert_eq!(app.current(&entries), Some(S("work/aws")));
+        press(&mut app, "k", &entries);
+        assert_eq!(app.current(&entries), Some(S("gitlab")));
+        app.key(KeyEvent::from(KeyCode::PageUp), &entries);
+        assert_eq!(app.selected, 0);
+    }
+
+    #[test]
+    fn test_filtering() {
+        let entries = pm::Entries::new()
+            .update(S("github"), pm::Entry{name: S("github"), ..pm::Entry::default()})
+            .update(S("gitlab"), pm::Entry{name: S("gitlab"), ..pm::Entry::default()})
+            .update(S("work/aws"), pm::Entry{name: S("work/aws"), username: S("admin"), ..pm::Entry::default()});
+        let mut app = App::default();
+        press(&mut app, "/git", &entries);
+        assert_eq!(app.names(&entries), vec![S("github"), S("gitlab")]);
+        // Typing goes in the filter, not to the other keys.
+        assert_eq!(press(&mut app, "q", &entries), Action::Nothing);
+        assert!(app.names(&entries).is_empty());
+        app.key(KeyEvent::from(KeyCode::Backspace), &entries);
+        app.key(KeyEvent::from(KeyCode::Enter), &entries);
+        assert!(!app.filtering);
+        // It's the username too, like `list --search`.
+        press(&mut app, "/", &entries);
+        app.key(KeyEvent::from(KeyCode::Esc), &entries);
+        press(&mut app, "/ADMIN", &entries);
+        app.key(KeyEvent::from(KeyCode::Enter), &entries);
+        assert_eq!(app.names(&entries), vec![S("work/aws")]);
+        app.key(KeyEvent::from(KeyCode::Esc), &entries);
+        assert_eq!(app.names(&entries).len(), 3);
+    }
+
+    #[test]
+    fn test_actions() {
+        let entries = pm::Entries::new()
+            .update(S("github"), pm::Entry{name: S("github"), ..pm::Entry::default()})
+            .update(S("gitlab"), pm::Entry{name: S("gitlab"), ..pm::Entry::default()});
+        let mut app = App::default();
+        assert_eq!(app.key(KeyEvent::from(KeyCode::Enter), &entries), Action::Copy(S("github"), "password"));
+        assert_eq!(press(&mut app, "ju", &entries), Action::Copy(S("gitlab"), "username"));
+        assert_eq!(press(&mut app, "e", &entries), Action::Edit(S("gitlab")));
+        assert_eq!(press(&mut app, "d", &entries), Action::Delete(S("gitlab")));
+        assert_eq!(press(&mut app, "a", &entries), Action::Add);
+        assert_eq!(press(&mut app, "q", &entries), Action::Quit);
+        assert_eq!(app.key(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL), &entries), Action::Quit);
+        // Nothing to act on.
+        press(&mut app, "/nope", &entries);
+        app.key(KeyEvent::from(KeyCode::Enter), &entries);
+        assert_eq!(press(&mut app, "d", &entries), Action::Nothing);
+    }
+
+    #[test]
+    fn test_details() {
+        let entry = pm::Entry{
+            name: S("github"),
+            username: S("octocat"),
+            password: S("hunter2"),
+            fields: pm::Fields::new().update(S("url"), S("https://github.com")).update(S("recovery"), S("1234")),
+            ..pm::Entry::default()
+        };
+        let masked = details(&entry, false);
+        assert!(masked.contains(&(S("Username"), S("octocat"))));
+        assert!(masked.contains(&(S("Password"), S("********"))));
+        assert!(masked.contains(&(S("Notes"), S(""))));
+        assert!(masked.contains(&(S("url"), S("https://github.com"))));
+        assert!(masked.contains(&(S("recovery"), S("********"))));
+        let revealed = details(&entry, true);
+        assert!(revealed.contains(&(S("Password"), S("hunter2"))));
+        assert!(revealed.contains(&(S("recovery"), S("1234"))));
+    }
+
+    #[test]
+    fn test_draw() {
+        let entries = pm::Entries::new().update(S("github"), pm::Entry{
+            name: S("github"),
+            username: S("octocat"),
+            password: S("hunter2"),
+            ..pm::Entry::default()
+        });
+        let mut terminal = ratatui::Terminal::new(ratatui::backend::TestBackend::new(80, 10)).unwrap();
+        let app = App::default();
+        terminal.draw(|frame| draw(frame, &app, &entries)).unwrap();
+        let screen: String = terminal.backend().buffer().content().iter().map(|cell| cell.symbol()).collect();
+        assert!(screen.contains("github"));
+        assert!(screen.contains("Username: octocat"));
+        assert!(screen.contains("Password: ********"));
+        assert!(!screen.contains("hunter2"));
+    }
+}
@@ -0,0 +1,284 @@
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use age::armor::{ArmoredReader, ArmoredWriter, Format};
+use age::secrecy::{ExposeSecret, SecretString};
+use age::x25519;
+
+use crate::{Entries, EntriesStuff};
+
+// A password file. It's plain JSON, like it's always been, until it has
+// recipients. Then it's a vault that only they can open, each with their own
+// key.
+//
+// The entries are an age file encrypted to every recipient's public key, so
+// the key that encrypts the data is wrapped once for each of them. Someone who
+// would rather use a passphrase than a key file gets a key made for them, and
+// that key is kept in the vault, encrypted with their passphrase. Everything
+// is encrypted again from scratch each time it's saved, so once someone is
+// removed, nothing saved after that is readable to them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Vault {
+    pub path: PathBuf,
+    // Nobody means it's plain JSON.
+    pub recipients: Vec<Recipient>,
+}
+
+#[derive(serde_derive::Serialize, serde_derive::Deserialize, Debug, Clone, PartialEq)]
+pub struct Recipient {
+    pub name: String,
+    pub public_key: String,
+    // For someone who opens the vault with a passphrase: the private key that
+    // goes with `public_key`, encrypted with it.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub passphrase_key: String,
+}
+
+// What's in the file once it's encrypted. The recipients are out in the open
+// so that they can be listed and so that passphrase users can find their key.
+#[derive(serde_derive::Serialize, serde_derive::Deserialize)]
+struct Locked {
+    vault: u32,
+    recipients: Vec<Recipient>,
+    entries: String,
+}
+
+// What's encrypted. The public keys are in here too so that anyone who can
+// write to the file, but not open it, can't add themselves to the list and
+// wait for the next save.
+#[derive(serde_derive::Serialize, serde_derive::Deserialize)]
+struct Payload {
+    recipients: Vec<String>,
+    entries: Entries,
+}
+
+impl Vault {
+    pub fn plain(path: &Path) -> Vault {
+        Vault{path: path.to_owned(), recipients: Vec::new()}
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        !self.recipients.is_empty()
+    }
+
+    // Opens a password file, whichever kind it is. A vault is tried with the
+    // key files first and then, if anyone uses a passphrase, with the one
+    // `passphrase` asks for.
+    pub fn open(path: &Path, identities: &[x25519::Identity], passphrase: &mut dyn FnMut() -> Result<String, String>) -> Result<(Vault, Entries), String> {
+        let bytes = std::fs::read(path).map_err(|e| format!("Failed reading {}: {}", path.display(), e))?;
+        let locked: Locked = match serde_json::from_slice(&bytes) {
+            Ok(locked) => locked,
+            Err(_) => return Ok((Vault::plain(path), Entries::load(path))),
+        };
+
+        let mut json = decrypt(&locked.entries, identities);
+        if json.is_err() && locked.recipients.iter().any(|recipient| !recipient.passphrase_key.is_empty()) {
+            let passphrase = passphrase()?;
+            let key = age::scrypt::Identity::new(SecretString::from(passphrase));
+            json = locked.recipients.iter()
+                .filter(|recipient| !recipient.passphrase_key.is_empty())
+                .find_map(|recipient| decrypt_with(&recipient.passphrase_key, &[&key]).ok())
+                .ok_or_else(|| String::from("That passphrase doesn't open the vault."))
+                .and_then(|key| parse_identity(&key))
+                .and_then(|identity| decrypt(&locked.entries, &[identity]));
+        }
+        let json = json.map_err(|_| format!("None of your keys open {}. Give me yours with --identity or PM_IDENTITY.", path.display()))?;
+        let payload: Payload = serde_json::from_slice(&json).map_err(|e| format!("The vault doesn't have entries in it: {}", e))?;
+
+        let public_keys: Vec<String> = locked.recipients.iter().map(|recipient| recipient.public_key.clone()).collect();
+        if public_keys != payload.recipients {
+            return Err(format!("The recipients of {} were changed outside of pm, so I won't trust it.", path.display()));
+        }
+        Ok((Vault{path: path.to_owned(), recipients: locked.recipients}, payload.entries))
+    }
+
+    pub fn save(&self, entries: &Entries) -> Result<(), String> {
+        if !self.is_encrypted() {
+            return entries.save(&self.path).map_err(|e| format!("Failed saving {}: {}", self.path.display(), e));
+        }
+        let public_keys: Vec<String> = self.recipients.iter().map(|recipient| recipient.public_key.clone()).collect();
+        let keys = public_keys.iter().map(|key| parse_public_key(key)).collect::<Result<Vec<_>, _>>()?;
+        let payload = serde_json::to_vec(&Payload{recipients: public_keys, entries: entries.clone()})
+            .expect("Failed serializing entries.");
+        let locked = Locked{
+            vault: 1,
+            recipients: self.recipients.clone(),
+            entries: encrypt(&payload, keys.iter().map(|key| key as &dyn age::Recipient))?,
+        };
+        let json = serde_json::to_string_pretty(&locked).expect("Failed serializing the vault.");
+        // It's written next to the vault and then moved over it, so a crash
+        // or a full disk partway through can't leave only half of it.
+        let name = self.path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        let temp = self.path.with_file_name(format!(".{}.{}.tmp", name, std::process::id()));
+        let mut file = crate::create_private(&temp, true)?;
+        let saved = file.write_all(json.as_bytes())
+            .and_then(|_| file.sync_all())
+            .and_then(|_| std::fs::rename(&temp, &self.path));
+        saved.map_err(|e| {
+            let _ = std::fs::remove_file(&temp);
+            format!("Failed saving {}: {}", self.path.display(), e)
+        })
+    }
+
+    // Adds someone who opens the vault with their own key.
+    pub fn add_key(&self, name: &str, public_key: &str) -> Result<Vault, String> {
+        let public_key = parse_public_key(public_key.trim())?.to_string();
+        self.add(Recipient{name: name.to_owned(), public_key, passphrase_key: String::new()})
+    }
+
+    // Adds someone who opens the vault with a passphrase.
+    pub fn add_passphrase(&self, name: &str, passphrase: &str) -> Result<Vault, String> {
+        if passphrase.is_empty() {
+            return Err(String::from("The passphrase can't be empty."));
+        }
+        let identity = x25519::Identity::generate();
+        let wrapping = age::scrypt::Recipient::new(SecretString::from(passphrase.to_owned()));
+        let passphrase_key = encrypt(identity.to_string().expose_secret().as_bytes(), std::iter::once(&wrapping as &dyn age::Recipient))?;
+        self.add(Recipient{name: name.to_owned(), public_key: identity.to_public().to_string(), passphrase_key})
+    }
+
+    fn add(&self, recipient: Recipient) -> Result<Vault, String> {
+        if recipient.name.trim().is_empty() {
+            return Err(String::from("Recipients need a name."));
+        }
+        if self.recipients.iter().any(|other| other.name == recipient.name) {
+            return Err(format!("There's already a recipient named \"{}\".", recipient.name));
+        }
+        if self.recipients.iter().any(|other| other.public_key == recipient.public_key) {
+            return Err(String::from("That key can already open the vault."));
+        }
+        let mut recipients = self.recipients.clone();
+        recipients.push(recipient);
+        Ok(Vault{path: self.path.clone(), recipients})
+    }
+
+    // Takes someone off the list. It's up to the caller to save, which is when
+    // it's encrypted again without them.
+    pub fn remove(&self, name: &str) -> Result<Vault, String> {
+        if !self.recipients.iter().any(|recipient| recipient.name == name) {
+            return Err(format!("There's no recipient named \"{}\".", name));
+        }
+        if self.recipients.len() == 1 {
+            return Err(format!("\"{}\" is the only one who can open the vault.", name));
+        }
+        let recipients = self.recipients.iter().filter(|recipient| recipient.name != name).cloned().collect();
+        Ok(Vault{path: self.path.clone(), recipients})
+    }
+}
+
+pub fn parse_public_key(text: &str) -> Result<x25519::Recipient, String> {
+    text.parse().map_err(|_| format!("\"{}\" isn't an age public key.", text))
+}
+
+fn parse_identity(text: &[u8]) -> Result<x25519::Identity, String> {
+    std::str::from_utf8(text).ok()
+        .and_then(|text| text.trim().parse().ok())
+        .ok_or_else(|| String::from("The vault has a broken passphrase key in it."))
+}
+
+// Encrypts to an ASCII-armored age file, so that it fits in JSON.
+fn encrypt<'a>(plaintext: &[u8], recipients: impl Iterator<Item = &'a dyn age::Recipient>) -> Result<String, String> {
+    let failed = |e: &dyn std::fmt::Display| format!("Failed encrypting the vault: {}", e);
+    let encryptor = age::Encryptor::with_recipients(recipients).map_err(|e| failed(&e))?;
+    let armored = ArmoredWriter::wrap_output(Vec::new(), Format::AsciiArmor).map_err(|e| failed(&e))?;
+    let mut writer = encryptor.wrap_output(armored).map_err(|e| failed(&e))?;
+    writer.write_all(plaintext).map_err(|e| failed(&e))?;
+    let armored = writer.finish().and_then(|armored| armored.finish()).map_err(|e| failed(&e))?;
+    Ok(String::from_utf8(armored).expect("Armor is ASCII."))
+}
+
+fn decrypt(armored: &str, identities: &[x25519::Identity]) -> Result<Vec<u8>, String> {
+    let identities: Vec<&dyn age::Identity> = identities.iter().map(|identity| identity as &dyn age::Identity).collect();
+    decrypt_with(armored, &identities)
+}
+
+fn decrypt_with(armored: &str, identities: &[&dyn age::Identity]) -> Result<Vec<u8>, String> {
+    let failed = |e: &dyn std::fmt::Display| format!("Failed decrypting the vault: {}", e);
+    let decryptor = age::Decryptor::new(ArmoredReader::new(armored.as_bytes())).map_err(|e| failed(&e))?;
+    let mut reader = decryptor.decrypt(identities.iter().copied()).map_err(|e| failed(&e))?;
+    let mut plaintext = Vec::new();
+    reader.read_to_end(&mut plaintext).map_err(|e| failed(&e))?;
+    Ok(plaintext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use crate::Entry;
+
+    static S: fn(&'static str) -> String = String::from;
+
+    fn no_passphrase() -> Result<String, String> {
+        Err(S("Nobody should have asked."))
+    }
+
+    #[test]
+    fn test_plain() {
+        let entries = Entries::new().update(S("a"), Entry{name: S("a"), password: S("a password"), ..Entry::default()});
+        let temp = mktemp::Temp::new_file().unwrap();
+        let vault = Vault::plain(&temp);
+        vault.save(&entries).unwrap();
+        assert_eq!(Entries::load(&temp), entries);
+        assert_eq!(Vault::open(&temp, &[], &mut no_passphrase), Ok((vault, entries.clone())));
+    }
+
+    #[test]
+    fn test_keys() {
+        let entries = Entries::new().update(S("a"), Entry{name: S("a"), password: S("a password"), ..Entry::default()});
+        let temp = mktemp::Temp::new_file().unwrap();
+        let (alice, bob, eve) = (x25519::Identity::generate(), x25519::Identity::generate(), x25519::Identity::generate());
+        let vault = Vault::plain(&temp)
+            .add_key("alice", &alice.to_public().to_string()).unwrap()
+            .add_key("bob", &bob.to_public().to_string()).unwrap();
+        assert!(vault.is_encrypted());
+        std::fs::set_permissions(&temp, std::fs::Permissions::from_mode(0o644)).unwrap();
+        vault.save(&entries).unwrap();
+        let text = std::fs::read_to_string(&temp).unwrap();
+        assert!(text.contains("-----BEGIN AGE ENCRYPTED FILE-----"));
+        // Only we can read it, and nothing is left next to it.
+        assert_eq!(std::fs::metadata(&temp).unwrap().permissions().mode() & 0o777, 0o600);
+        let name = temp.file_name().unwrap().to_string_lossy().into_owned();
+        assert!(!temp.with_file_name(format!(".{}.{}.tmp", name, std::process::id())).exists());
+        assert!(!text.contains("a password"));
+
+        assert_eq!(Vault::open(&temp, std::slice::from_ref(&alice), &mut no_passphrase), Ok((vault.clone(), entries.clone())));
+        assert_eq!(Vault::open(&temp, &[eve.clone(), bob.clone()], &mut no_passphrase).map(|(_, entries)| entries), Ok(entries.clone()));
+        assert!(Vault::open(&temp, std::slice::from_ref(&eve), &mut no_passphrase).is_err());
+
+        // Once bob is removed and it's saved, his key doesn't work anymore.
+        let vault = vault.remove("bob").unwrap();
+        vault.save(&entries).unwrap();
+        assert!(Vault::open(&temp, std::slice::from_ref(&bob), &mut no_passphrase).is_err());
+        assert!(Vault::open(&temp, std::slice::from_ref(&alice), &mut no_passphrase).is_ok());
+
+        assert!(vault.remove("alice").is_err());
+        assert!(vault.remove("carol").is_err());
+        assert!(vault.add_key("alice", &eve.to_public().to_string()).is_err());
+        assert!(vault.add_key("eve", &alice.to_public().to_string()).is_err());
+        assert!(vault.add_key("eve", "age1nope").is_err());
+
+        // Someone who can only write to the file can't add themselves.
+        let mut locked: Locked = serde_json::from_str(&std::fs::read_to_string(&temp).unwrap()).unwrap();
+        locked.recipients.push(Recipient{name: S("eve"), public_key: eve.to_public().to_string(), passphrase_key: S("")});
+        std::fs::write(&temp, serde_json::to_string(&locked).unwrap()).unwrap();
+        assert!(Vault::open(&temp, &[alice], &mut no_passphrase).unwrap_err().contains("changed outside of pm"));
+    }
+
+    #[test]
+    fn test_passphrase() {
+        let entries = Entries::new().update(S("a"), Entry{name: S("a"), password: S("a password"), ..Entry::default()});
+        let temp = mktemp::Temp::new_file().unwrap();
+        let alice = x25519::Identity::generate();
+        let vault = Vault::plain(&temp)
+            .add_key("alice", &alice.to_public().to_string()).unwrap()
+            .add_passphrase("bob", "correct horse").unwrap();
+        assert!(Vault::plain(&temp).add_passphrase("bob", "").is_err());
+        vault.save(&entries).unwrap();
+
+        // Keys are tried before asking for a passphrase.
+        assert!(Vault::open(&temp, &[alice], &mut no_passphrase).is_ok());
+        assert_eq!(Vault::open(&temp, &[], &mut || Ok(S("correct horse"))), Ok((vault, entries.clone())));
+        assert!(Vault::open(&temp, &[], &mut || Ok(S("battery staple"))).is_err());
+    }
+}